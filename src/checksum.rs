@@ -1,10 +1,172 @@
+use std::fmt::{self, Display, Formatter};
 use std::path::{Path, PathBuf};
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::str::FromStr;
 use anyhow::Result;
 use digest::{Digest, DynDigest};
-use crate::cmd_line::Algorithm;
+use flate2::read::MultiGzDecoder;
+use itertools::join;
+use memmap2::Mmap;
 use crate::error::AppError;
+use crate::keyed::Keyed;
+use crate::non_crypto::{Adler32, Blake3, Crc32, XxHash64, Xxh3128};
+
+/// Size of the buffer for reading files that aren't memory mapped.
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Files at least this large are hashed through a memory map instead of a read loop.
+const MMAP_THRESHOLD: u64 = 16 * 1024 * 1024;
+
+/// Memory mapped files are read in and hashed this much at a time.
+const MMAP_CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Gets every read from a file to do and may hold something meanwhile, e.g. a permit to read.
+/// Hashing what was read happens outside of it.
+pub(crate) type ReadGate = dyn Fn(&mut dyn FnMut()) + Send + Sync;
+
+/// Reads right away, for when nothing limits reading.
+fn ungated(read: &mut dyn FnMut()) {
+    read()
+}
+
+/// Does each read through a gate.
+struct Gated<'a, R> {
+    reader: R,
+    gate: &'a ReadGate,
+}
+
+impl<R: Read> Read for Gated<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let (reader, mut result) = (&mut self.reader, Ok(0));
+        (self.gate)(&mut || result = reader.read(buf));
+        result
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Algorithm {
+    MD5,
+    SHA1,
+    SHA224,
+    #[default]
+    SHA256,
+    SHA384,
+    SHA512,
+    /// SHA-512 with its own initial values, truncated to 256 bits. Not the same as SHA-256.
+    SHA512_256,
+    /// SHA-512 with its own initial values, truncated to 224 bits. Not the same as SHA-224.
+    SHA512_224,
+    BLAKE2B,
+    BLAKE2S,
+    BLAKE3,
+    WHIRLPOOL,
+    CRC32,
+    ADLER32,
+    XXH64,
+    XXH3_128,
+}
+
+impl Algorithm {
+    /// Size of the digest in bytes, HMACs have the same.
+    pub fn digest_size(self) -> usize {
+        get_hasher(self).output_size()
+    }
+}
+
+/// What the tool knows about an algorithm besides how to compute it.
+#[derive(Debug)]
+pub struct AlgorithmInfo {
+    pub algorithm: Algorithm,
+    /// As written to checksum files and headers.
+    pub name: &'static str,
+    /// Other spellings accepted, like the name, in any case.
+    pub aliases: &'static [&'static str],
+    /// Tried for checksums of its length when none is given, even if other algorithms have the same
+    /// length, so plain `md5sum` or `sha256sum` output still verifies.
+    pub guessable: bool,
+}
+
+const fn info(algorithm: Algorithm, name: &'static str, aliases: &'static [&'static str], guessable: bool) -> AlgorithmInfo {
+    AlgorithmInfo { algorithm, name, aliases, guessable }
+}
+
+/// Every algorithm, in the order of the enum. At most one algorithm of each length is guessable:
+/// Adler-32 has the same size as CRC32, XXH3-128 as MD5, BLAKE2s/BLAKE3/BLAKE2b as SHA-256/SHA-512,
+/// SHA-512/256 and SHA-512/224 as SHA-256 and SHA-224 and Whirlpool as SHA-512, so they always need
+/// to be given explicitly, with `-a` or a header.
+pub const ALGORITHMS: &[AlgorithmInfo] = &[
+    info(Algorithm::MD5, "MD5", &[], true),
+    info(Algorithm::SHA1, "SHA1", &["SHA-1"], true),
+    info(Algorithm::SHA224, "SHA224", &["SHA-224"], true),
+    info(Algorithm::SHA256, "SHA256", &["SHA-256"], true),
+    info(Algorithm::SHA384, "SHA384", &["SHA-384"], true),
+    info(Algorithm::SHA512, "SHA512", &["SHA-512"], true),
+    info(Algorithm::SHA512_256, "SHA512/256", &["SHA-512/256", "SHA512_256"], false),
+    info(Algorithm::SHA512_224, "SHA512/224", &["SHA-512/224", "SHA512_224"], false),
+    info(Algorithm::BLAKE2B, "BLAKE2b", &["BLAKE2B512", "BLAKE2B-512", "B2"], false),
+    info(Algorithm::BLAKE2S, "BLAKE2s", &["BLAKE2S256", "BLAKE2S-256"], false),
+    info(Algorithm::BLAKE3, "BLAKE3", &["B3"], false),
+    info(Algorithm::WHIRLPOOL, "WHIRLPOOL", &[], false),
+    info(Algorithm::CRC32, "CRC32", &["CRC-32"], true),
+    info(Algorithm::ADLER32, "ADLER32", &["ADLER-32"], false),
+    info(Algorithm::XXH64, "XXH64", &["XXHASH64"], true),
+    info(Algorithm::XXH3_128, "XXH128", &["XXH3_128", "XXH3-128", "XXH3"], false),
+];
+
+impl Algorithm {
+    pub fn info(self) -> &'static AlgorithmInfo {
+        ALGORITHMS.iter().find(|i| i.algorithm == self).expect("Internal error.")
+    }
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.info().name)
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ALGORITHMS.iter()
+            .find(|i| i.name.eq_ignore_ascii_case(s) || i.aliases.iter().any(|a| a.eq_ignore_ascii_case(s)))
+            .map(|i| i.algorithm)
+            .ok_or_else(|| AppError::InvalidAlgorithmError(s.to_owned()))
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum Encoding {
+    #[default]
+    Hex,
+    HexUpper,
+    Base64,
+}
+
+impl Display for Encoding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Encoding::Hex => "hex",
+            Encoding::HexUpper => "hex-upper",
+            Encoding::Base64 => "base64",
+        })
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "hex" | "hex-lower" => Encoding::Hex,
+            "hex-upper" => Encoding::HexUpper,
+            "base64" => Encoding::Base64,
+            _ => return Err(AppError::InvalidEncodingError(s.to_owned()))
+        })
+    }
+}
 
 fn get_hasher(algorithm: Algorithm) -> Box<dyn DynDigest> {
     match algorithm {
@@ -14,85 +176,644 @@ fn get_hasher(algorithm: Algorithm) -> Box<dyn DynDigest> {
         Algorithm::SHA256 => Box::new(sha2::Sha256::new()),
         Algorithm::SHA384 => Box::new(sha2::Sha384::new()),
         Algorithm::SHA512 => Box::new(sha2::Sha512::new()),
+        Algorithm::SHA512_256 => Box::new(sha2::Sha512Trunc256::new()),
+        Algorithm::SHA512_224 => Box::new(sha2::Sha512Trunc224::new()),
+        Algorithm::BLAKE2B => Box::new(blake2::Blake2b::new()),
+        Algorithm::BLAKE2S => Box::new(blake2::Blake2s::new()),
+        Algorithm::BLAKE3 => Box::new(Blake3::default()),
+        Algorithm::WHIRLPOOL => Box::new(whirlpool::Whirlpool::new()),
+        Algorithm::CRC32 => Box::new(Crc32::default()),
+        Algorithm::ADLER32 => Box::new(Adler32::default()),
+        Algorithm::XXH64 => Box::new(XxHash64::default()),
+        Algorithm::XXH3_128 => Box::new(Xxh3128::default()),
     }
 }
 
-fn guess_algorithm(hash_size: usize) -> Result<Algorithm> {
-    match hash_size {
-        16 => Ok(Algorithm::MD5),
-        20 => Ok(Algorithm::SHA1),
-        28 => Ok(Algorithm::SHA224),
-        32 => Ok(Algorithm::SHA256),
-        48 => Ok(Algorithm::SHA384),
-        64 => Ok(Algorithm::SHA512),
-        _ => Err(AppError::UnknownAlgorithmError(hash_size * 8))?
+fn get_keyed_hasher(algorithm: Algorithm, key: &[u8]) -> Result<Box<dyn DynDigest>> {
+    Ok(match algorithm {
+        Algorithm::MD5 => Box::new(Keyed::<md5::Md5>::new(key)),
+        Algorithm::SHA1 => Box::new(Keyed::<sha1::Sha1>::new(key)),
+        Algorithm::SHA224 => Box::new(Keyed::<sha2::Sha224>::new(key)),
+        Algorithm::SHA256 => Box::new(Keyed::<sha2::Sha256>::new(key)),
+        Algorithm::SHA384 => Box::new(Keyed::<sha2::Sha384>::new(key)),
+        Algorithm::SHA512 => Box::new(Keyed::<sha2::Sha512>::new(key)),
+        Algorithm::SHA512_256 => Box::new(Keyed::<sha2::Sha512Trunc256>::new(key)),
+        Algorithm::SHA512_224 => Box::new(Keyed::<sha2::Sha512Trunc224>::new(key)),
+        Algorithm::BLAKE2B => Box::new(Keyed::<blake2::Blake2b>::new(key)),
+        Algorithm::BLAKE2S => Box::new(Keyed::<blake2::Blake2s>::new(key)),
+        Algorithm::WHIRLPOOL => Box::new(Keyed::<whirlpool::Whirlpool>::new(key)),
+        // HMAC is only defined over block-based hash functions, BLAKE3 has a keyed mode of its own instead.
+        _ => return Err(AppError::UnkeyableAlgorithmError(algorithm.to_string()).into()),
+    })
+}
+
+/// Picks the algorithm by the checksum length, refusing to choose between several that fit.
+pub fn guess_algorithm(hash_size: usize) -> Result<Algorithm> {
+    let candidates: Vec<Algorithm> = ALGORITHMS.iter()
+        .filter(|i| i.algorithm.digest_size() == hash_size)
+        .map(|i| i.algorithm)
+        .collect();
+    match candidates[..] {
+        [algorithm] => Ok(algorithm),
+        [] => Err(AppError::UnknownAlgorithmError(hash_size))?,
+        _ => Err(AppError::AmbiguousAlgorithmError(hash_size, join(candidates.iter(), ", ")))?,
     }
 }
 
+/// The algorithm a checksum of this length is verified with when none is given: the only one of
+/// the length, or else the guessable one among them.
+pub fn likely_algorithm(hash_size: usize) -> Option<Algorithm> {
+    guess_algorithm(hash_size).ok().or_else(|| {
+        ALGORITHMS.iter().find(|i| i.guessable && i.algorithm.digest_size() == hash_size).map(|i| i.algorithm)
+    })
+}
+
+/// Parses hex digits, with an optional `0x` in front as hex editors and some tools write them.
 fn str_to_bytes(s: &str) -> Result<Vec<u8>> {
-    if s.len() / 2 * 2 != s.len() {
+    let digits = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some("") => Err(AppError::InvalidHashValue(s.to_owned()))?,
+        Some(digits) => digits,
+        None => s,
+    };
+    if digits.len() / 2 * 2 != digits.len() {
         Err(AppError::InvalidHashValue(s.to_owned()))?;
     }
-    let mut buf = Vec::<u8>::with_capacity(s.len() / 2);
-    for idx in (0..s.len()).step_by(2) {
-        let u8 = u8::from_str_radix(&s[idx..idx + 2], 16).or(Err(AppError::InvalidHashValue(s.to_owned())))?;
+    let mut buf = Vec::<u8>::with_capacity(digits.len() / 2);
+    for idx in (0..digits.len()).step_by(2) {
+        let u8 = u8::from_str_radix(&digits[idx..idx + 2], 16).or(Err(AppError::InvalidHashValue(s.to_owned())))?;
         buf.push(u8);
     }
     Ok(buf)
 }
 
-pub fn calculate_checksum(path: &Path, algorithm: Algorithm) -> Result<Vec<u8>> {
-    let mut hasher = get_hasher(algorithm);
-    let mut buffer = [0; 4096];
-    let mut f = File::open(path)?;
+/// Decodes a hex or base64 checksum, trying hex first when no encoding is given.
+pub fn decode_checksum(s: &str, encoding: Option<Encoding>) -> Result<Vec<u8>> {
+    match encoding {
+        // Hex digits are accepted in either case, whichever was used for writing.
+        Some(Encoding::Hex) | Some(Encoding::HexUpper) => str_to_bytes(s),
+        Some(Encoding::Base64) => base64::decode(s).or(Err(AppError::InvalidHashValue(s.to_owned()).into())),
+        // Hex takes precedence, base64 is only tried when the value isn't valid hex.
+        None => str_to_bytes(s).or_else(|_| decode_checksum(s, Some(Encoding::Base64))),
+    }
+}
+
+pub fn encode_checksum(checksum: &[u8], encoding: Encoding) -> String {
+    match encoding {
+        Encoding::Hex => join(checksum.iter().map(|b| format!("{:02x}", b)), ""),
+        Encoding::HexUpper => join(checksum.iter().map(|b| format!("{:02X}", b)), ""),
+        Encoding::Base64 => base64::encode(checksum),
+    }
+}
+
+fn get_hashers(algorithms: &[Algorithm], key: Option<&[u8]>) -> Result<Vec<Box<dyn DynDigest>>> {
+    match key {
+        Some(key) => algorithms.iter().map(|a| get_keyed_hasher(*a, key)).collect(),
+        None => Ok(algorithms.iter().map(|a| get_hasher(*a)).collect()),
+    }
+}
+
+fn calculate_reader_checksums(reader: &mut dyn Read, algorithms: &[Algorithm], key: Option<&[u8]>, buffer_size: usize) -> Result<Vec<Vec<u8>>> {
+    let mut hashers = get_hashers(algorithms, key)?;
+    let mut buffer = vec![0; buffer_size.max(1)];
     loop {
-        let n = f.read(&mut buffer)?;
+        let n = reader.read(&mut buffer)?;
         if n == 0 {
             break;
         }
-        hasher.update(&buffer[0..n]);
+        for hasher in hashers.iter_mut() {
+            hasher.update(&buffer[0..n]);
+        }
     }
-    Ok(Vec::from(hasher.finalize()))
+    Ok(hashers.into_iter().map(|h| Vec::from(h.finalize())).collect())
+}
+
+fn calculate_slice_checksums(data: &[u8], algorithms: &[Algorithm], key: Option<&[u8]>, gate: &ReadGate) -> Result<Vec<Vec<u8>>> {
+    let mut hashers = get_hashers(algorithms, key)?;
+    // BLAKE3 hashes a tree of chunks, so even a single large file is spread over all cores.
+    // The others are sequential and can only go through the data in order.
+    let mut trees: Vec<Option<blake3::Hasher>> = algorithms.iter()
+        .map(|a| (*a == Algorithm::BLAKE3).then(blake3::Hasher::new))
+        .collect();
+    for chunk in data.chunks(MMAP_CHUNK_SIZE) {
+        // Touching every page reads the chunk in, so hashing it doesn't wait for the disk.
+        gate(&mut || {
+            std::hint::black_box(chunk.iter().step_by(4096).fold(0u8, |a, b| a ^ b));
+        });
+        for (hasher, tree) in hashers.iter_mut().zip(trees.iter_mut()) {
+            match tree {
+                Some(tree) => {
+                    tree.update_rayon(chunk);
+                }
+                None => hasher.update(chunk),
+            }
+        }
+    }
+    Ok(hashers.into_iter().zip(trees).map(|(hasher, tree)| match tree {
+        Some(tree) => tree.finalize().as_bytes().to_vec(),
+        None => Vec::from(hasher.finalize()),
+    }).collect())
+}
+
+fn calculate_mmap_checksums(file: &File, algorithms: &[Algorithm], key: Option<&[u8]>, gate: &ReadGate) -> Option<Result<Vec<Vec<u8>>>> {
+    // Safety: the map is read-only and doesn't outlive this call. Another process truncating the
+    // file meanwhile can still crash us with SIGBUS, which is the known price of mmap.
+    let map = unsafe { Mmap::map(file) }.ok()?;
+    Some(calculate_slice_checksums(&map, algorithms, key, gate))
 }
 
-pub fn verify_checksum(path: &Path, checksum: &str, algorithm: Option<Algorithm>) -> Result<(PathBuf, bool)> {
-    let algorithm = algorithm.unwrap_or(guess_algorithm(checksum.len() / 2)?);
-    let calculated = calculate_checksum(path, algorithm);
-    Ok((path.to_owned(), str_to_bytes(checksum)? == calculated?))
+/// Calculates checksums with all given algorithms in a single pass over the file, as HMACs if a key is given.
+///
+/// Files of at least `MMAP_THRESHOLD` bytes are memory mapped, falling back to buffered reading if
+/// mapping fails. Mapped files are hashed with BLAKE3 in parallel.
+pub fn calculate_checksums(path: &Path, algorithms: &[Algorithm], key: Option<&[u8]>, buffer_size: usize) -> Result<Vec<Vec<u8>>> {
+    calculate_file_checksums(path, algorithms, key, buffer_size, false, ByteRange::default(), &ungated)
+}
+
+/// Part of a file to hash, the whole file by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ByteRange {
+    pub offset: u64,
+    /// Up to the end of the file when not given.
+    pub length: Option<u64>,
+}
+
+/// Like `calculate_checksums`, but only hashes the bytes in `range`. A range past the end of the
+/// file is cut short, possibly to nothing.
+pub fn calculate_range_checksums(path: &Path, algorithms: &[Algorithm], key: Option<&[u8]>, buffer_size: usize, range: ByteRange) -> Result<Vec<Vec<u8>>> {
+    calculate_file_checksums(path, algorithms, key, buffer_size, false, range, &ungated)
+}
+
+/// Like `calculate_checksums`, but a `.gz` file is hashed by its decompressed content rather than
+/// the compressed bytes on disk.
+pub fn calculate_decompressed_checksums(path: &Path, algorithms: &[Algorithm], key: Option<&[u8]>, buffer_size: usize) -> Result<Vec<Vec<u8>>> {
+    calculate_file_checksums(path, algorithms, key, buffer_size, true, ByteRange::default(), &ungated)
+}
+
+/// What the public `calculate_*` functions share, with every read from the file done through
+/// `gate`. `range` is only taken when not decompressing.
+pub(crate) fn calculate_file_checksums(path: &Path, algorithms: &[Algorithm], key: Option<&[u8]>, buffer_size: usize, decompress: bool, range: ByteRange, gate: &ReadGate) -> Result<Vec<Vec<u8>>> {
+    let mut file = File::open(path)?;
+    if decompress && path.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("gz")) {
+        // Members concatenated into one file are decompressed one after another, as `gzip -d` does.
+        return calculate_reader_checksums(&mut MultiGzDecoder::new(Gated { reader: file, gate }), algorithms, key, buffer_size);
+    }
+    if !decompress && range != ByteRange::default() {
+        // Reading past the end of a file gives nothing rather than an error.
+        file.seek(SeekFrom::Start(range.offset))?;
+        return calculate_reader_checksums(&mut Gated { reader: file.take(range.length.unwrap_or(u64::MAX)), gate }, algorithms, key, buffer_size);
+    }
+    if file.metadata()?.len() >= MMAP_THRESHOLD {
+        if let Some(result) = calculate_mmap_checksums(&file, algorithms, key, gate) {
+            return result;
+        }
+    }
+    calculate_reader_checksums(&mut Gated { reader: file, gate }, algorithms, key, buffer_size)
+}
+
+pub fn calculate_checksum_bytes(data: &[u8], algorithm: Algorithm) -> Vec<u8> {
+    let mut hasher = get_hasher(algorithm);
+    hasher.update(data);
+    Vec::from(hasher.finalize())
+}
+
+pub fn calculate_checksum(path: &Path, algorithm: Algorithm) -> Result<Vec<u8>> {
+    Ok(calculate_checksums(path, &[algorithm], None, DEFAULT_BUFFER_SIZE)?.remove(0))
+}
+
+pub fn verify_checksum(path: &Path, checksum: &str, algorithm: Option<Algorithm>, encoding: Option<Encoding>, key: Option<&[u8]>) -> Result<(PathBuf, bool)> {
+    verify_range_checksum(path, checksum, algorithm, encoding, key, ByteRange::default())
+}
+
+/// Like `verify_checksum`, for the checksum of only the bytes in `range`.
+pub fn verify_range_checksum(path: &Path, checksum: &str, algorithm: Option<Algorithm>, encoding: Option<Encoding>, key: Option<&[u8]>, range: ByteRange) -> Result<(PathBuf, bool)> {
+    let expected = decode_checksum(checksum, encoding)?;
+    let algorithm = match algorithm {
+        // Such a checksum can never match, there is no point reading the file.
+        Some(algorithm) if algorithm.digest_size() != expected.len() => {
+            return Err(AppError::DigestLengthError(algorithm.to_string(), algorithm.digest_size(), expected.len()).into());
+        }
+        Some(algorithm) => algorithm,
+        // Lengths several algorithms share are checked with the guessable one, an error only
+        // comes when none of them is.
+        None => match likely_algorithm(expected.len()) {
+            Some(algorithm) => algorithm,
+            None => guess_algorithm(expected.len())?,
+        },
+    };
+    let calculated = calculate_range_checksums(path, &[algorithm], key, DEFAULT_BUFFER_SIZE, range)?.remove(0);
+    Ok((path.to_owned(), expected == calculated))
+}
+
+pub fn verify_size(path: &Path, size: u64) -> Result<bool> {
+    Ok(path.metadata()?.len() == size)
 }
 
 #[cfg(test)]
 mod test {
     use tempfile::NamedTempFile;
     use std::io::Write;
-    use crate::checksum::verify_checksum;
-    use crate::cmd_line::Algorithm;
+    use crate::checksum::{
+        ALGORITHMS, DEFAULT_BUFFER_SIZE, MMAP_THRESHOLD, Algorithm, ByteRange, Encoding, calculate_checksum_bytes, calculate_checksums,
+        calculate_decompressed_checksums, calculate_range_checksums, calculate_reader_checksums, decode_checksum, encode_checksum, guess_algorithm, likely_algorithm,
+        verify_checksum,
+    };
+    use crate::error::AppError;
 
     #[test]
     fn test_checksum() {
         let mut file = NamedTempFile::new().unwrap();
-        file.write("abcdABCD1234".as_bytes()).unwrap();
+        file.write_all("abcdABCD1234".as_bytes()).unwrap();
+        file.flush().unwrap();
+        let path = file.path();
+        assert!(verify_checksum(path, "bb057481a1b7abc93ad5d70d52e3a55f", None, None, None).unwrap().1);
+        assert!(verify_checksum(path, "a9c0f8c056a19fdfd18db386039bdc90e680116c", None, None, None).unwrap().1);
+        assert!(verify_checksum(path, "1815e1f3522b385698aec88f13f880e838264fbd3f90f6e25f22fd8e", None, None, None).unwrap().1);
+        assert!(verify_checksum(path, "423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b", None, None, None).unwrap().1);
+        assert!(verify_checksum(path, "9732f0a3c0a4cb8d834111224681e516534e74d5062e67bc5f652e5c5684d5b01795781bd5e51fdf0aeb1e13abd5004e", None, None, None).unwrap().1);
+        assert!(verify_checksum(path, "56e36f3eb1a36bef4d8665f17efe30a52f190bdbaff24be9f73ed18cdbab41b09eca3256967a1b5da04d2b501e7d3cd4b0fbe55a0e64ae905aefe8676a7aaa9d", None, None, None).unwrap().1);
+
+        assert!(verify_checksum(path, "bb057481a1b7abc93ad5d70d52e3a55f", Some(Algorithm::MD5), None, None).unwrap().1);
+        assert!(verify_checksum(path, "a9c0f8c056a19fdfd18db386039bdc90e680116c", Some(Algorithm::SHA1), None, None).unwrap().1);
+        assert!(verify_checksum(path, "1815e1f3522b385698aec88f13f880e838264fbd3f90f6e25f22fd8e", Some(Algorithm::SHA224), None, None).unwrap().1);
+        assert!(verify_checksum(path, "423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b", Some(Algorithm::SHA256), None, None).unwrap().1);
+        assert!(verify_checksum(path, "9732f0a3c0a4cb8d834111224681e516534e74d5062e67bc5f652e5c5684d5b01795781bd5e51fdf0aeb1e13abd5004e", Some(Algorithm::SHA384), None, None).unwrap().1);
+        assert!(verify_checksum(path, "56e36f3eb1a36bef4d8665f17efe30a52f190bdbaff24be9f73ed18cdbab41b09eca3256967a1b5da04d2b501e7d3cd4b0fbe55a0e64ae905aefe8676a7aaa9d", Some(Algorithm::SHA512), None, None).unwrap().1);
+
+        assert!(!verify_checksum(path, "0b057481a1b7abc93ad5d70d52e3a55f", None, None, None).unwrap().1);
+        assert!(!verify_checksum(path, "09c0f8c056a19fdfd18db386039bdc90e680116c", None, None, None).unwrap().1);
+        assert!(!verify_checksum(path, "0815e1f3522b385698aec88f13f880e838264fbd3f90f6e25f22fd8e", None, None, None).unwrap().1);
+        assert!(!verify_checksum(path, "023df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b", None, None, None).unwrap().1);
+        assert!(!verify_checksum(path, "0732f0a3c0a4cb8d834111224681e516534e74d5062e67bc5f652e5c5684d5b01795781bd5e51fdf0aeb1e13abd5004e", None, None, None).unwrap().1);
+        assert!(!verify_checksum(path, "06e36f3eb1a36bef4d8665f17efe30a52f190bdbaff24be9f73ed18cdbab41b09eca3256967a1b5da04d2b501e7d3cd4b0fbe55a0e64ae905aefe8676a7aaa9d", None, None, None).unwrap().1);
+        // A checksum too short or long for the given algorithm is an error rather than a mismatch.
+        let err = verify_checksum(path, "bb057481a1b7abc93ad5d70d52e3a55f", Some(Algorithm::SHA256), None, None).unwrap_err();
+        assert!(matches!(err.downcast_ref::<AppError>(), Some(AppError::DigestLengthError(_, 32, 16))));
+    }
+
+    #[test]
+    fn test_base64_checksum() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all("abcdABCD1234".as_bytes()).unwrap();
         file.flush().unwrap();
         let path = file.path();
-        assert!(verify_checksum(path, "bb057481a1b7abc93ad5d70d52e3a55f", None).unwrap().1);
-        assert!(verify_checksum(path, "a9c0f8c056a19fdfd18db386039bdc90e680116c", None).unwrap().1);
-        assert!(verify_checksum(path, "1815e1f3522b385698aec88f13f880e838264fbd3f90f6e25f22fd8e", None).unwrap().1);
-        assert!(verify_checksum(path, "423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b", None).unwrap().1);
-        assert!(verify_checksum(path, "9732f0a3c0a4cb8d834111224681e516534e74d5062e67bc5f652e5c5684d5b01795781bd5e51fdf0aeb1e13abd5004e", None).unwrap().1);
-        assert!(verify_checksum(path, "56e36f3eb1a36bef4d8665f17efe30a52f190bdbaff24be9f73ed18cdbab41b09eca3256967a1b5da04d2b501e7d3cd4b0fbe55a0e64ae905aefe8676a7aaa9d", None).unwrap().1);
-
-        assert!(verify_checksum(path, "bb057481a1b7abc93ad5d70d52e3a55f", Some(Algorithm::MD5)).unwrap().1);
-        assert!(verify_checksum(path, "a9c0f8c056a19fdfd18db386039bdc90e680116c", Some(Algorithm::SHA1)).unwrap().1);
-        assert!(verify_checksum(path, "1815e1f3522b385698aec88f13f880e838264fbd3f90f6e25f22fd8e", Some(Algorithm::SHA224)).unwrap().1);
-        assert!(verify_checksum(path, "423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b", Some(Algorithm::SHA256)).unwrap().1);
-        assert!(verify_checksum(path, "9732f0a3c0a4cb8d834111224681e516534e74d5062e67bc5f652e5c5684d5b01795781bd5e51fdf0aeb1e13abd5004e", Some(Algorithm::SHA384)).unwrap().1);
-        assert!(verify_checksum(path, "56e36f3eb1a36bef4d8665f17efe30a52f190bdbaff24be9f73ed18cdbab41b09eca3256967a1b5da04d2b501e7d3cd4b0fbe55a0e64ae905aefe8676a7aaa9d", Some(Algorithm::SHA512)).unwrap().1);
-
-        assert!(!verify_checksum(path, "0b057481a1b7abc93ad5d70d52e3a55f", None).unwrap().1);
-        assert!(!verify_checksum(path, "09c0f8c056a19fdfd18db386039bdc90e680116c", None).unwrap().1);
-        assert!(!verify_checksum(path, "0815e1f3522b385698aec88f13f880e838264fbd3f90f6e25f22fd8e", None).unwrap().1);
-        assert!(!verify_checksum(path, "023df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b", None).unwrap().1);
-        assert!(!verify_checksum(path, "0732f0a3c0a4cb8d834111224681e516534e74d5062e67bc5f652e5c5684d5b01795781bd5e51fdf0aeb1e13abd5004e", None).unwrap().1);
-        assert!(!verify_checksum(path, "06e36f3eb1a36bef4d8665f17efe30a52f190bdbaff24be9f73ed18cdbab41b09eca3256967a1b5da04d2b501e7d3cd4b0fbe55a0e64ae905aefe8676a7aaa9d", None).unwrap().1);
+        // MD5 and SHA-1 digests need "==" and "=" padding, SHA-384 needs none.
+        assert!(verify_checksum(path, "uwV0gaG3q8k61dcNUuOlXw==", None, None, None).unwrap().1);
+        assert!(verify_checksum(path, "qcD4wFahn9/RjbOGA5vckOaAEWw=", None, None, None).unwrap().1);
+        assert!(verify_checksum(path, "Qj3w2rapfEYjnRlq1vYQ7fVIRlDp5whWNAReiz/BnQs=", None, Some(Encoding::Base64), None).unwrap().1);
+        assert!(verify_checksum(path, "lzLwo8Cky42DQREiRoHlFlNOdNUGLme8X2UuXFaE1bAXlXgb1eUf3wrrHhOr1QBO", None, None, None).unwrap().1);
+        assert!(verify_checksum(path, "lzLwo8Cky42DQREiRoHlFlNOdNUGLme8X2UuXFaE1bAXlXgb1eUf3wrrHhOr1QBO", Some(Algorithm::SHA384), Some(Encoding::Base64), None).unwrap().1);
+        assert!(!verify_checksum(path, "AAAAAAAAAAAAAAAAAAAAAA==", None, None, None).unwrap().1);
+        assert!(verify_checksum(path, "Qj3w2rapfEYjnRlq1vYQ7fVIRlDp5whWNAReiz/BnQs==", None, None, None).is_err());
+        assert!(verify_checksum(path, "QpbOqs/Geu!!", None, None, None).is_err());
+        assert!(verify_checksum(path, "bb057481a1b7abc93ad5d70d52e3a55f", None, Some(Encoding::Base64), None).is_err());
+    }
+
+    #[test]
+    fn test_encode_checksum() {
+        let bytes = [0xbbu8, 0x05, 0x74, 0x81];
+        assert_eq!(encode_checksum(&bytes, Encoding::Hex), "bb057481");
+        assert_eq!(encode_checksum(&bytes, Encoding::HexUpper), "BB057481");
+        assert_eq!(encode_checksum(&bytes, Encoding::Base64), "uwV0gQ==");
+        assert_eq!(decode_checksum("BB057481", None).unwrap(), bytes);
+        assert_eq!(decode_checksum("bB057481", Some(Encoding::Hex)).unwrap(), bytes);
+        assert_eq!("hex-lower".parse::<Encoding>().unwrap(), Encoding::Hex);
+        assert_eq!("HEX-UPPER".parse::<Encoding>().unwrap(), Encoding::HexUpper);
+        assert_eq!(decode_checksum("uwV0gQ==", None).unwrap(), bytes);
+        assert_eq!(decode_checksum("bb057481", None).unwrap(), bytes);
+        // Missing padding is tolerated, misplaced padding is not.
+        assert_eq!(decode_checksum("uwV0gQ", Some(Encoding::Base64)).unwrap(), bytes);
+        assert!(decode_checksum("uw=V0gQ=", Some(Encoding::Base64)).is_err());
+    }
+
+    #[test]
+    fn test_hex_prefix() {
+        let bytes = [0xbbu8, 0x05, 0x74, 0x81];
+        assert_eq!(decode_checksum("0xbb057481", None).unwrap(), bytes);
+        assert_eq!(decode_checksum("0XBB057481", Some(Encoding::Hex)).unwrap(), bytes);
+        assert_eq!(decode_checksum("0xBB057481", Some(Encoding::HexUpper)).unwrap(), bytes);
+        for malformed in ["0x", "0xbb05748", "0xbb05748g", "0x0xbb057481", "x0bb057481"] {
+            assert!(decode_checksum(malformed, Some(Encoding::Hex)).is_err(), "{}", malformed);
+        }
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all("abcdABCD1234".as_bytes()).unwrap();
+        file.flush().unwrap();
+        for checksum in ["bb057481a1b7abc93ad5d70d52e3a55f", "0xbb057481a1b7abc93ad5d70d52e3a55f", "0XBB057481A1B7ABC93AD5D70D52E3A55F"] {
+            let (_, matched) = verify_checksum(file.path(), checksum, None, None, None).unwrap();
+            assert!(matched, "{}", checksum);
+        }
+        assert!(!verify_checksum(file.path(), "0xbb057481a1b7abc93ad5d70d52e3a55e", None, None, None).unwrap().1);
+    }
+
+    #[test]
+    fn test_multiple_checksums() {
+        // A byte slice can only be consumed once, so all digests must come from the same pass.
+        let mut reader = "abcdABCD1234".as_bytes();
+        let checksums = calculate_reader_checksums(&mut reader, &[Algorithm::SHA256, Algorithm::MD5, Algorithm::SHA512], None, DEFAULT_BUFFER_SIZE).unwrap();
+        assert_eq!(checksums.len(), 3);
+        assert_eq!(encode_checksum(&checksums[0], Encoding::Hex), "423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b");
+        assert_eq!(encode_checksum(&checksums[1], Encoding::Hex), "bb057481a1b7abc93ad5d70d52e3a55f");
+        assert_eq!(encode_checksum(&checksums[2], Encoding::Hex), "56e36f3eb1a36bef4d8665f17efe30a52f190bdbaff24be9f73ed18cdbab41b09eca3256967a1b5da04d2b501e7d3cd4b0fbe55a0e64ae905aefe8676a7aaa9d");
+    }
+
+    #[test]
+    fn test_non_crypto_checksum() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all("abcdABCD1234".as_bytes()).unwrap();
+        file.flush().unwrap();
+        let path = file.path();
+        assert!(verify_checksum(path, "537eb9fd", None, None, None).unwrap().1);
+        assert!(verify_checksum(path, "537eb9fd", Some(Algorithm::CRC32), None, None).unwrap().1);
+        assert!(verify_checksum(path, "18e0035f", Some(Algorithm::ADLER32), None, None).unwrap().1);
+        assert!(!verify_checksum(path, "18e0035f", None, None, None).unwrap().1);
+
+        assert_eq!(encode_checksum(&calculate_checksum_bytes(b"123456789", Algorithm::CRC32), Encoding::Hex), "cbf43926");
+        assert_eq!(encode_checksum(&calculate_checksum_bytes(b"Wikipedia", Algorithm::ADLER32), Encoding::Hex), "11e60398");
+    }
+
+    #[test]
+    fn test_adler32_checksum() {
+        // Values from zlib's `adler32()`, the last one large enough for both sums to wrap around.
+        let hex = |data: &[u8]| encode_checksum(&calculate_checksum_bytes(data, Algorithm::ADLER32), Encoding::Hex);
+        assert_eq!(hex(b""), "00000001");
+        assert_eq!(hex(b"abc"), "024d0127");
+        assert_eq!(hex(&[0xff; 100_000]), "149a302c");
+        assert_eq!(Algorithm::ADLER32.digest_size(), 4);
+        // The length is CRC32's, which is tried when no algorithm is given.
+        assert_eq!(likely_algorithm(4), Some(Algorithm::CRC32));
+    }
+
+    #[test]
+    fn test_algorithm_table() {
+        // Doesn't compile once a variant is added, which then needs its place in the table too.
+        let position = |algorithm| match algorithm {
+            Algorithm::MD5 => 0,
+            Algorithm::SHA1 => 1,
+            Algorithm::SHA224 => 2,
+            Algorithm::SHA256 => 3,
+            Algorithm::SHA384 => 4,
+            Algorithm::SHA512 => 5,
+            Algorithm::SHA512_256 => 6,
+            Algorithm::SHA512_224 => 7,
+            Algorithm::BLAKE2B => 8,
+            Algorithm::BLAKE2S => 9,
+            Algorithm::BLAKE3 => 10,
+            Algorithm::WHIRLPOOL => 11,
+            Algorithm::CRC32 => 12,
+            Algorithm::ADLER32 => 13,
+            Algorithm::XXH64 => 14,
+            Algorithm::XXH3_128 => 15,
+        };
+        assert_eq!(ALGORITHMS.len(), 16);
+        for (i, info) in ALGORITHMS.iter().enumerate() {
+            assert_eq!(position(info.algorithm), i);
+            for spelling in Some(&info.name).into_iter().chain(info.aliases) {
+                assert_eq!(spelling.parse::<Algorithm>().unwrap(), info.algorithm);
+            }
+        }
+    }
+
+    #[test]
+    fn test_algorithm_names() {
+        for algorithm in &[Algorithm::MD5, Algorithm::SHA1, Algorithm::SHA224, Algorithm::SHA256, Algorithm::SHA384,
+            Algorithm::SHA512, Algorithm::SHA512_256, Algorithm::SHA512_224, Algorithm::BLAKE2B, Algorithm::BLAKE2S,
+            Algorithm::BLAKE3, Algorithm::WHIRLPOOL, Algorithm::CRC32, Algorithm::ADLER32, Algorithm::XXH64, Algorithm::XXH3_128] {
+            assert_eq!(algorithm.to_string().parse::<Algorithm>().unwrap(), *algorithm);
+            assert_eq!(algorithm.to_string().to_lowercase().parse::<Algorithm>().unwrap(), *algorithm);
+        }
+        assert_eq!("crc-32".parse::<Algorithm>().unwrap(), Algorithm::CRC32);
+        assert_eq!("blake2b-512".parse::<Algorithm>().unwrap(), Algorithm::BLAKE2B);
+        assert_eq!("BLAKE2s256".parse::<Algorithm>().unwrap(), Algorithm::BLAKE2S);
+        assert_eq!("Adler-32".parse::<Algorithm>().unwrap(), Algorithm::ADLER32);
+        assert_eq!("SHA-512/256".parse::<Algorithm>().unwrap(), Algorithm::SHA512_256);
+        assert_eq!("sha512_224".parse::<Algorithm>().unwrap(), Algorithm::SHA512_224);
+        assert!("crc64".parse::<Algorithm>().is_err());
+        assert_eq!(Algorithm::BLAKE2S.digest_size(), 32);
+        assert_eq!(Algorithm::XXH3_128.digest_size(), 16);
+    }
+
+    #[test]
+    fn test_blake2_checksum() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all("abc".as_bytes()).unwrap();
+        file.flush().unwrap();
+        let path = file.path();
+        // Test vectors from RFC 7693, appendix A and B.
+        let blake2b = "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d17d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923";
+        let blake2s = "508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982";
+        assert!(verify_checksum(path, blake2b, Some(Algorithm::BLAKE2B), None, None).unwrap().1);
+        assert!(verify_checksum(path, blake2s, Some(Algorithm::BLAKE2S), None, None).unwrap().1);
+        // The lengths collide with SHA-512 and SHA-256, so guessing picks the SHA variants.
+        assert!(!verify_checksum(path, blake2b, None, None, None).unwrap().1);
+        assert!(!verify_checksum(path, blake2s, None, None, None).unwrap().1);
+    }
+
+    #[test]
+    fn test_guess_algorithm() {
+        // Each guessable algorithm needs a length of its own among the guessable ones, or its
+        // checksums couldn't be verified without '-a' anymore.
+        for info in ALGORITHMS.iter().filter(|i| i.guessable) {
+            assert_eq!(likely_algorithm(info.algorithm.digest_size()), Some(info.algorithm));
+        }
+        assert_eq!(guess_algorithm(20).unwrap(), Algorithm::SHA1);
+        let e = guess_algorithm(3).unwrap_err();
+        assert!(matches!(e.downcast_ref::<AppError>(), Some(AppError::UnknownAlgorithmError(3))));
+        assert_eq!(likely_algorithm(3), None);
+
+        // Every algorithm of the length is a candidate, the guessable one included.
+        let e = guess_algorithm(32).unwrap_err();
+        match e.downcast_ref::<AppError>() {
+            Some(AppError::AmbiguousAlgorithmError(32, candidates)) => assert_eq!(candidates, "SHA256, SHA512/256, BLAKE2s, BLAKE3"),
+            _ => panic!("Unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn test_verify_ambiguous_checksum() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"abc").unwrap();
+        file.flush().unwrap();
+        let path = file.path();
+        let sha256 = encode_checksum(&calculate_checksum_bytes(b"abc", Algorithm::SHA256), Encoding::Hex);
+        let blake3 = encode_checksum(&calculate_checksum_bytes(b"abc", Algorithm::BLAKE3), Encoding::Hex);
+        // Plain `sha256sum` output still verifies without '-a', anything else of the length is
+        // checked as SHA-256 too and fails.
+        assert!(verify_checksum(path, &sha256, None, None, None).unwrap().1);
+        assert!(!verify_checksum(path, &blake3, None, None, None).unwrap().1);
+        assert!(verify_checksum(path, &blake3, Some(Algorithm::BLAKE3), None, None).unwrap().1);
+        assert!(!verify_checksum(path, &blake3, Some(Algorithm::SHA256), None, None).unwrap().1);
+    }
+
+    #[test]
+    fn test_sha512_truncated_checksum() {
+        // Test vectors from the NIST examples for FIPS 180-4.
+        let hex = |data: &[u8], algorithm| encode_checksum(&calculate_checksum_bytes(data, algorithm), Encoding::Hex);
+        assert_eq!(hex(b"abc", Algorithm::SHA512_256), "53048e2681941ef99b2e29b76b4c7dabe4c2d0c634fc6d46e0e2f13107e7af23");
+        assert_eq!(hex(b"abc", Algorithm::SHA512_224), "4634270f707b6a54daae7530460842e20e37ed265ceee9a43e8924aa");
+        // The lengths collide with SHA-256 and SHA-224, which are tried when no algorithm is given.
+        assert_eq!(likely_algorithm(Algorithm::SHA512_256.digest_size()), Some(Algorithm::SHA256));
+        assert_eq!(likely_algorithm(Algorithm::SHA512_224.digest_size()), Some(Algorithm::SHA224));
+    }
+
+    #[test]
+    fn test_whirlpool_checksum() {
+        // Test vectors from the ISO/IEC 10118-3 reference set.
+        let hex = |data: &[u8]| encode_checksum(&calculate_checksum_bytes(data, Algorithm::WHIRLPOOL), Encoding::Hex);
+        assert_eq!(hex(b""), "19fa61d75522a4669b44e39c1d2e1726c530232130d407f89afee0964997f7a73e83be698b288febcf88e3e03c4f0757ea8964e59b63d93708b138cc42a66eb3");
+        assert_eq!(hex(b"abc"), "4e2448a4c6f486bb16b6562c73b4020bf3043e3a731bce721ae1b303d97e6d4c7181eebdb6c57e277d0e34957114cbd6c797fc9d95d8b582d225292076d4eef5");
+        // The length is SHA-512's, which is tried when no algorithm is given.
+        assert_eq!(likely_algorithm(Algorithm::WHIRLPOOL.digest_size()), Some(Algorithm::SHA512));
+    }
+
+    #[test]
+    fn test_blake3_checksum() {
+        // Test vectors from the BLAKE3 reference implementation.
+        let hex = |data: &[u8]| encode_checksum(&calculate_checksum_bytes(data, Algorithm::BLAKE3), Encoding::Hex);
+        assert_eq!(hex(b""), "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262");
+        assert_eq!(hex(b"abc"), "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+        assert_eq!("b3".parse::<Algorithm>().unwrap(), Algorithm::BLAKE3);
+        assert_eq!(likely_algorithm(Algorithm::BLAKE3.digest_size()), Some(Algorithm::SHA256));
+
+        // Large files are hashed in parallel, which must agree with the read loop.
+        let data: Vec<u8> = (0..MMAP_THRESHOLD * 2 + 12345).map(|i| (i % 251) as u8).collect();
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&data).unwrap();
+        file.flush().unwrap();
+        let algorithms = [Algorithm::BLAKE3, Algorithm::SHA256];
+        let mapped = calculate_checksums(file.path(), &algorithms, None, DEFAULT_BUFFER_SIZE).unwrap();
+        let buffered = calculate_reader_checksums(&mut data.as_slice(), &algorithms, None, DEFAULT_BUFFER_SIZE).unwrap();
+        assert_eq!(mapped, buffered);
+        assert_eq!(mapped[0], blake3::hash(&data).as_bytes());
+        // There is no HMAC over BLAKE3.
+        assert!(calculate_checksums(file.path(), &[Algorithm::BLAKE3], Some(b"key"), DEFAULT_BUFFER_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_xxhash_checksum() {
+        // Reference values from the xxHash implementation.
+        let hex = |data: &[u8], algorithm| encode_checksum(&calculate_checksum_bytes(data, algorithm), Encoding::Hex);
+        assert_eq!(hex(b"", Algorithm::XXH64), "ef46db3751d8e999");
+        assert_eq!(hex(b"abc", Algorithm::XXH64), "44bc2cf5ad770999");
+        assert_eq!(hex(b"", Algorithm::XXH3_128), "99aa06d3014798d86001c324468d497f");
+        assert_eq!(hex(b"abc", Algorithm::XXH3_128), "06b05ab6733a618578af5f94892f3950");
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all("abc".as_bytes()).unwrap();
+        file.flush().unwrap();
+        let path = file.path();
+        assert!(verify_checksum(path, "44bc2cf5ad770999", None, None, None).unwrap().1);
+        assert!(verify_checksum(path, "06b05ab6733a618578af5f94892f3950", Some(Algorithm::XXH3_128), None, None).unwrap().1);
+        assert!(!verify_checksum(path, "06b05ab6733a618578af5f94892f3950", None, None, None).unwrap().1);
+    }
+
+    #[test]
+    fn test_hmac_checksum() {
+        // Test vectors from RFC 4231, test cases 1, 2 and 6.
+        let hmac = |key: &[u8], data: &str, algorithm| {
+            let checksums = calculate_reader_checksums(&mut data.as_bytes(), &[algorithm], Some(key), DEFAULT_BUFFER_SIZE).unwrap();
+            encode_checksum(&checksums[0], Encoding::Hex)
+        };
+        assert_eq!(hmac(&[0x0b; 20], "Hi There", Algorithm::SHA256), "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7");
+        assert_eq!(hmac(b"Jefe", "what do ya want for nothing?", Algorithm::SHA256), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+        assert_eq!(hmac(b"Jefe", "what do ya want for nothing?", Algorithm::SHA512), "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737");
+        assert_eq!(hmac(&[0xaa; 131], "Test Using Larger Than Block-Size Key - Hash Key First", Algorithm::SHA256), "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54");
+
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all("Hi There".as_bytes()).unwrap();
+        file.flush().unwrap();
+        let path = file.path();
+        let expected = "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7";
+        assert!(verify_checksum(path, expected, None, None, Some(&[0x0b; 20])).unwrap().1);
+        assert!(!verify_checksum(path, expected, None, None, Some(&[0x0c; 20])).unwrap().1);
+        assert!(!verify_checksum(path, expected, None, None, None).unwrap().1);
+        // Non-cryptographic checksums have no HMAC construction.
+        assert!(calculate_checksums(path, &[Algorithm::CRC32], Some(b"Jefe"), DEFAULT_BUFFER_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_mmap_checksum() {
+        // Just above the threshold, so the file is mapped, compared against the read loop.
+        let data: Vec<u8> = (0..MMAP_THRESHOLD + 4097).map(|i| (i % 251) as u8).collect();
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&data).unwrap();
+        file.flush().unwrap();
+        let algorithms = [Algorithm::SHA256, Algorithm::CRC32];
+        let mapped = calculate_checksums(file.path(), &algorithms, None, DEFAULT_BUFFER_SIZE).unwrap();
+        let buffered = calculate_reader_checksums(&mut data.as_slice(), &algorithms, None, DEFAULT_BUFFER_SIZE).unwrap();
+        assert_eq!(mapped, buffered);
+
+        // Small files, including empty ones which can't be mapped, take the buffered path.
+        let empty = NamedTempFile::new().unwrap();
+        assert_eq!(encode_checksum(&calculate_checksums(empty.path(), &algorithms, None, DEFAULT_BUFFER_SIZE).unwrap()[0], Encoding::Hex),
+                   "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    }
+
+    #[test]
+    fn test_buffer_size() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&data).unwrap();
+        file.flush().unwrap();
+        let expected = calculate_checksums(file.path(), &[Algorithm::SHA256], None, DEFAULT_BUFFER_SIZE).unwrap();
+        for buffer_size in &[1, 7, 4096, 1024 * 1024] {
+            assert_eq!(calculate_checksums(file.path(), &[Algorithm::SHA256], None, *buffer_size).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_decompressed_checksums() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("data.txt");
+        std::fs::write(&plain, "abcdABCD1234").unwrap();
+        // Two members, as `cat a.gz b.gz` gives.
+        let mut compressed = Vec::new();
+        for part in ["abcd", "ABCD1234"] {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(part.as_bytes()).unwrap();
+            compressed.extend(encoder.finish().unwrap());
+        }
+        let gz = dir.path().join("data.txt.gz");
+        std::fs::write(&gz, &compressed).unwrap();
+        let md5 = |path, decompress| {
+            let checksums = match decompress {
+                true => calculate_decompressed_checksums(path, &[Algorithm::MD5], None, DEFAULT_BUFFER_SIZE),
+                false => calculate_checksums(path, &[Algorithm::MD5], None, DEFAULT_BUFFER_SIZE),
+            };
+            encode_checksum(&checksums.unwrap()[0], Encoding::Hex)
+        };
+
+        assert_eq!(md5(&gz, true), "bb057481a1b7abc93ad5d70d52e3a55f");
+        assert_eq!(md5(&gz, true), md5(&plain, false));
+        assert_ne!(md5(&gz, false), md5(&plain, false));
+        // Other files are read as they are.
+        assert_eq!(md5(&plain, true), md5(&plain, false));
+        // Not actually compressed.
+        let fake = dir.path().join("fake.gz");
+        std::fs::write(&fake, "abcd").unwrap();
+        assert!(calculate_decompressed_checksums(&fake, &[Algorithm::MD5], None, DEFAULT_BUFFER_SIZE).is_err());
+    }
+
+    #[test]
+    fn test_range_checksums() {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(b"xxxxabcdABCD1234yyyy").unwrap();
+        file.flush().unwrap();
+        let md5 = |offset, length| {
+            let checksums = calculate_range_checksums(file.path(), &[Algorithm::MD5], None, DEFAULT_BUFFER_SIZE, ByteRange { offset, length });
+            encode_checksum(&checksums.unwrap()[0], Encoding::Hex)
+        };
+        let expected = |data: &[u8]| encode_checksum(&calculate_checksum_bytes(data, Algorithm::MD5), Encoding::Hex);
+
+        assert_eq!(md5(4, Some(12)), "bb057481a1b7abc93ad5d70d52e3a55f");
+        assert_eq!(md5(0, None), expected(b"xxxxabcdABCD1234yyyy"));
+        assert_eq!(md5(16, None), expected(b"yyyy"));
+        assert_eq!(md5(0, Some(4)), expected(b"xxxx"));
+        // Lengths past the end are cut short, offsets past it leave nothing to hash.
+        assert_eq!(md5(16, Some(100)), expected(b"yyyy"));
+        assert_eq!(md5(100, Some(4)), expected(b""));
+        assert_eq!(md5(4, Some(0)), expected(b""));
     }
 }
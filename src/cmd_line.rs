@@ -1,8 +1,14 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::convert::TryFrom;
+use std::fs;
 use std::path::PathBuf;
 use std::num::ParseIntError;
 use std::str::FromStr;
+use globset::Glob;
 use structopt::StructOpt;
-use crate::error::AppError;
+use structopt::clap::Shell;
+use chksum::{DEFAULT_BUFFER_SIZE, AppError, Algorithm, Encoding, decode_checksum};
+use crate::template::Template;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "chksum", about = "A tool to generate and verify file checksums.", author = "Chen Xu <windoze@0d0a.com>", rename_all = "kebab-case")]
@@ -16,25 +22,172 @@ pub struct GenerationOpt {
     #[structopt(name = "CHECKSUMS", short = "f", parse(from_os_str), default_value = "checksums.txt")]
     pub checksum_file: PathBuf,
 
-    #[structopt(short, default_value)]
-    pub algorithm: Algorithm,
+    #[structopt(short, long, default_value = "SHA256", use_delimiter = true)]
+    pub algorithm: Vec<Algorithm>,
 
     #[structopt(short, default_value)]
     pub num_threads: ThreadNum,
 
+    /// Zero means as many as there are threads.
+    #[structopt(long, default_value = "0")]
+    pub io_concurrency: usize,
+
+    #[structopt(long)]
+    pub strict: bool,
+
+    #[structopt(long, default_value)]
+    pub buffer_size: BufferSize,
+
+    #[structopt(long, visible_alias = "format", default_value)]
+    pub output_format: OutputFormat,
+
+    #[structopt(long, default_value)]
+    pub path_style: PathStyle,
+
+    /// Same as `--path-style unix`.
+    #[structopt(long, visible_alias = "posix-paths")]
+    pub slash: bool,
+
+    #[structopt(long, default_value)]
+    pub path_mode: PathMode,
+
+    /// Writes paths relative to this directory, files outside of it keep their whole path.
+    #[structopt(long, parse(from_os_str), conflicts_with = "path-mode")]
+    pub strip_prefix: Option<PathBuf>,
+
+    #[structopt(long, default_value)]
+    pub encoding: Encoding,
+
+    #[structopt(long)]
+    pub format_template: Option<Template>,
+
+    #[structopt(short, long)]
+    pub zero: bool,
+
+    #[structopt(short, long)]
+    pub binary: bool,
+
+    #[structopt(long, env = "CHKSUM_HMAC_KEY", hide_env_values = true)]
+    pub hmac_key: Option<HmacKey>,
+
+    #[structopt(long)]
+    pub no_progress: bool,
+
+    #[structopt(long)]
+    pub stats: bool,
+
+    #[structopt(long)]
+    pub decompress: bool,
+
+    #[structopt(long, default_value = "0")]
+    pub offset: u64,
+
+    #[structopt(long)]
+    pub length: Option<u64>,
+
+    #[structopt(long)]
+    pub header: bool,
+
+    #[structopt(long)]
+    pub append: bool,
+
+    #[structopt(long)]
+    pub update: bool,
+
+    #[structopt(long)]
+    pub resume: bool,
+
+    #[structopt(long)]
+    pub with_size: bool,
+
+    /// Starts GNU style lines with the algorithm, so verifying never has to guess it.
+    #[structopt(long, conflicts_with = "with-size")]
+    pub tag_algorithm: bool,
+
+    #[structopt(long)]
+    pub per_directory: bool,
+
+    #[structopt(long, visible_alias = "respect-gitignore")]
+    pub gitignore: bool,
+
+    #[structopt(long = "include", number_of_values = 1)]
+    pub include_globs: Vec<Glob>,
+
+    #[structopt(long = "exclude", number_of_values = 1)]
+    pub exclude_globs: Vec<Glob>,
+
+    /// Extensions of files to skip, e.g. `tmp,part,swp`, in any case and with or without the dot.
+    #[structopt(long = "exclude-ext", number_of_values = 1, use_delimiter = true)]
+    pub exclude_extensions: Vec<String>,
+
+    #[structopt(long, overrides_with = "no-follow-symlinks")]
+    pub follow_symlinks: bool,
+
+    #[structopt(long, overrides_with = "follow-symlinks", visible_alias = "no-follow-links")]
+    pub no_follow_symlinks: bool,
+
+    #[structopt(long, overrides_with = "cross-file-system")]
+    pub one_file_system: bool,
+
+    #[structopt(long, overrides_with = "one-file-system", visible_alias = "cross-filesystems")]
+    pub cross_file_system: bool,
+
+    #[structopt(long)]
+    pub no_hidden: bool,
+
+    #[structopt(long)]
+    pub max_depth: Option<usize>,
+
+    #[structopt(long, default_value = "0")]
+    pub min_depth: usize,
+
+    #[structopt(long, overrides_with = "no-recursive")]
+    pub recursive: bool,
+
+    #[structopt(long, overrides_with = "recursive")]
+    pub no_recursive: bool,
+
     #[structopt(short = "x", parse(from_os_str), default_value = "-")]
     pub exclude: Vec<PathBuf>,
 
-    #[structopt(parse(from_os_str), default_value = ".")]
+    #[structopt(long, parse(from_os_str))]
+    pub files_from: Option<PathBuf>,
+
+    /// The `--files-from` list is NUL-delimited, as `find -print0` writes it, without changing the output like `-z`.
+    #[structopt(short = "0", long)]
+    pub null: bool,
+
+    #[structopt(long, visible_alias = "min-filesize")]
+    pub min_size: Option<FileSize>,
+
+    #[structopt(long, visible_alias = "max-filesize")]
+    pub max_size: Option<FileSize>,
+
+    /// Another directory to walk, the same as listing it after the options.
+    #[structopt(short = "d", long = "directory", parse(from_os_str), number_of_values = 1)]
+    pub directories: Vec<PathBuf>,
+
+    #[structopt(parse(from_os_str))]
     pub directory: Vec<PathBuf>,
 }
 
+impl GenerationOpt {
+    /// The directories to walk, those listed after the options first, the current one if none is given.
+    pub fn roots(&self) -> Vec<PathBuf> {
+        match self.directory.iter().chain(&self.directories).cloned().collect::<Vec<_>>() {
+            roots if roots.is_empty() => vec![PathBuf::from(".")],
+            roots => roots,
+        }
+    }
+}
+
 #[derive(Clone, Debug, StructOpt)]
 pub struct VerificationOpt {
-    #[structopt(name = "CHECKSUMS", short = "f", parse(from_os_str), default_value = "checksums.txt")]
-    pub checksum_file: PathBuf,
+    /// Repeated to verify several checksum files in one go, e.g. `-f md5sums.txt -f sha256sums.txt`.
+    #[structopt(name = "CHECKSUMS", short = "f", parse(from_os_str), number_of_values = 1, default_value = "checksums.txt")]
+    pub checksum_files: Vec<PathBuf>,
 
-    #[structopt(short)]
+    #[structopt(short, long)]
     pub algorithm: Option<Algorithm>,
 
     #[structopt(short, default_value)]
@@ -42,8 +195,130 @@ pub struct VerificationOpt {
 
     #[structopt(short)]
     pub quiet: bool,
+
+    #[structopt(short, long, conflicts_with = "quiet")]
+    pub verbose: bool,
+
+    #[structopt(long)]
+    pub ignore_missing: bool,
+
+    #[structopt(long)]
+    pub strict: bool,
+
+    #[structopt(short, long)]
+    pub warn: bool,
+
+    #[structopt(long)]
+    pub no_summary: bool,
+
+    #[structopt(long)]
+    pub mixed: bool,
+
+    #[structopt(long)]
+    pub fail_fast: bool,
+
+    #[structopt(long)]
+    pub encoding: Option<Encoding>,
+
+    #[structopt(long, default_value)]
+    pub input_format: InputFormat,
+
+    #[structopt(long, default_value)]
+    pub format: ReportFormat,
+
+    #[structopt(short, long)]
+    pub zero: bool,
+
+    #[structopt(long, env = "CHKSUM_HMAC_KEY", hide_env_values = true)]
+    pub hmac_key: Option<HmacKey>,
+
+    #[structopt(long)]
+    pub per_directory: bool,
+
+    #[structopt(short = "d", long, visible_alias = "root", parse(from_os_str))]
+    pub base_dir: Option<PathBuf>,
+
+    #[structopt(long, number_of_values = 1)]
+    pub only: Vec<Glob>,
+
+    #[structopt(long, visible_alias = "audit", parse(from_os_str))]
+    pub check_extra: Option<PathBuf>,
+
+    #[structopt(long, requires = "check-extra")]
+    pub strict_extra: bool,
+
+    #[structopt(parse(from_os_str), default_value = ".")]
+    pub directory: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct DuplicateOpt {
+    #[structopt(short, long, default_value = "SHA256")]
+    pub algorithm: Algorithm,
+
+    #[structopt(short, default_value)]
+    pub num_threads: ThreadNum,
+
+    #[structopt(long)]
+    pub min_size: Option<FileSize>,
+
+    #[structopt(parse(from_os_str), default_value = ".")]
+    pub directory: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct CompareOpt {
+    #[structopt(short, long, default_value = "SHA256")]
+    pub algorithm: Algorithm,
+
+    #[structopt(short, default_value)]
+    pub num_threads: ThreadNum,
+
+    #[structopt(parse(from_os_str))]
+    pub dir_a: PathBuf,
+
+    #[structopt(parse(from_os_str))]
+    pub dir_b: PathBuf,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct CheckOpt {
+    #[structopt(short, long)]
+    pub algorithm: Option<Algorithm>,
+
+    #[structopt(long, default_value = "0")]
+    pub offset: u64,
+
+    #[structopt(long)]
+    pub length: Option<u64>,
+
+    #[structopt(parse(from_os_str))]
+    pub file: PathBuf,
+
+    /// Read from stdin when `-`.
+    pub checksum: String,
+}
+
+#[derive(Clone, Debug, StructOpt)]
+pub struct DiffOpt {
+    #[structopt(short, long)]
+    pub algorithm: Option<Algorithm>,
+
+    #[structopt(long, default_value)]
+    pub input_format: InputFormat,
+
+    #[structopt(short, long)]
+    pub zero: bool,
+
+    #[structopt(parse(from_os_str))]
+    pub file_a: PathBuf,
+
+    #[structopt(parse(from_os_str))]
+    pub file_b: PathBuf,
 }
 
+// Parsed once at startup, the size of the options doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, StructOpt)]
 #[structopt(rename_all = "kebab-case")]
 pub enum Commands {
@@ -56,6 +331,37 @@ pub enum Commands {
         #[structopt(flatten)]
         verification_opts: VerificationOpt,
     },
+
+    #[structopt(visible_alias = "dup")]
+    D {
+        #[structopt(flatten)]
+        duplicate_opts: DuplicateOpt,
+    },
+
+    Cmp {
+        #[structopt(flatten)]
+        compare_opts: CompareOpt,
+    },
+
+    C {
+        #[structopt(flatten)]
+        check_opts: CheckOpt,
+    },
+
+    Diff {
+        #[structopt(flatten)]
+        diff_opts: DiffOpt,
+    },
+
+    /// Lists the supported algorithms.
+    #[structopt(visible_alias = "list-algorithms")]
+    Algorithms,
+
+    /// Prints the completion script for a shell.
+    Completions {
+        #[structopt(possible_values = &Shell::variants(), case_insensitive = true)]
+        shell: Shell,
+    },
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -67,23 +373,24 @@ impl Default for ThreadNum {
     }
 }
 
-impl ToString for ThreadNum {
-    fn to_string(&self) -> String {
-        self.0.to_string()
+impl Display for ThreadNum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
 impl FromStr for ThreadNum {
     type Err = ParseIntError;
 
+    /// Zero means the default, i.e. one thread per physical core.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        usize::from_str(s).map(|v| Self(v))
+        usize::from_str(s).map(|n| if n == 0 { Self::default() } else { Self(n) })
     }
 }
 
-impl Into<usize> for ThreadNum {
-    fn into(self) -> usize {
-        self.0
+impl From<ThreadNum> for usize {
+    fn from(v: ThreadNum) -> Self {
+        v.0
     }
 }
 
@@ -93,52 +400,304 @@ impl From<usize> for ThreadNum {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-pub enum Algorithm {
-    MD5,
-    SHA1,
-    SHA224,
-    SHA256,
-    SHA384,
-    SHA512,
-}
+/// A size in bytes, optionally with a binary unit suffix, e.g. `64K` or `1MiB`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BufferSize(usize);
 
-impl Default for Algorithm {
+impl Default for BufferSize {
     fn default() -> Self {
-        Algorithm::SHA256
+        Self(DEFAULT_BUFFER_SIZE)
+    }
+}
+
+impl Display for BufferSize {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            n if n % (1 << 30) == 0 => write!(f, "{}G", n >> 30),
+            n if n % (1 << 20) == 0 => write!(f, "{}M", n >> 20),
+            n if n % (1 << 10) == 0 => write!(f, "{}K", n >> 10),
+            n => write!(f, "{}", n),
+        }
+    }
+}
+
+impl FromStr for BufferSize {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_size(s).ok()
+            .and_then(|n| usize::try_from(n.0).ok())
+            .filter(|n| *n > 0)
+            .map(Self)
+            .ok_or_else(|| AppError::InvalidSizeError(s.to_owned()))
+    }
+}
+
+impl From<BufferSize> for usize {
+    fn from(v: BufferSize) -> Self {
+        v.0
+    }
+}
+
+/// A file size in bytes for filtering, with the same suffixes as `BufferSize`. Unlike a buffer
+/// it can be 0, and it can be larger than memory.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct FileSize(u64);
+
+impl FromStr for FileSize {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_size(s)
+    }
+}
+
+impl From<FileSize> for u64 {
+    fn from(v: FileSize) -> Self {
+        v.0
+    }
+}
+
+fn parse_size(s: &str) -> Result<FileSize, AppError> {
+    let upper = s.to_uppercase();
+    let number = upper.strip_suffix("IB").or_else(|| upper.strip_suffix('B')).unwrap_or(&upper);
+    let (number, shift) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 10),
+        Some('M') => (&number[..number.len() - 1], 20),
+        Some('G') => (&number[..number.len() - 1], 30),
+        _ => (number, 0),
+    };
+    number.trim().parse::<u64>().ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .map(FileSize)
+        .ok_or_else(|| AppError::InvalidSizeError(s.to_owned()))
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Gnu,
+    Hashdeep,
+    Csv,
+    /// A JSON array of objects, one per checksum.
+    Json,
+    /// The same objects as `Json`, one per line.
+    Jsonl,
+}
+
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OutputFormat::Gnu => "gnu",
+            OutputFormat::Hashdeep => "hashdeep",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Jsonl => "jsonl",
+        })
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "gnu" => OutputFormat::Gnu,
+            "hashdeep" => OutputFormat::Hashdeep,
+            "csv" => OutputFormat::Csv,
+            "json" => OutputFormat::Json,
+            "jsonl" | "ndjson" => OutputFormat::Jsonl,
+            _ => return Err(AppError::InvalidOutputFormatError(s.to_owned()))
+        })
     }
 }
 
-impl ToString for Algorithm {
-    fn to_string(&self) -> String {
-        match self {
-            Algorithm::MD5 => "MD5",
-            Algorithm::SHA1 => "SHA1",
-            Algorithm::SHA224 => "SHA224",
-            Algorithm::SHA256 => "SHA256",
-            Algorithm::SHA384 => "SHA384",
-            Algorithm::SHA512 => "SHA512",
-        }.to_string()
+/// Tagged and hashdeep files are detected automatically when reading the default GNU format.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum InputFormat {
+    #[default]
+    Gnu,
+    Csv,
+}
+
+impl Display for InputFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            InputFormat::Gnu => "gnu",
+            InputFormat::Csv => "csv",
+        })
     }
 }
 
-impl FromStr for Algorithm {
+impl FromStr for InputFormat {
     type Err = AppError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        Ok(match s.to_uppercase().as_str() {
-            "MD5" => Algorithm::MD5,
-            "SHA1" => Algorithm::SHA1,
-            "SHA224" => Algorithm::SHA224,
-            "SHA256" => Algorithm::SHA256,
-            "SHA384" => Algorithm::SHA384,
-            "SHA512" => Algorithm::SHA512,
-            "SHA-1" => Algorithm::SHA1,
-            "SHA-224" => Algorithm::SHA224,
-            "SHA-256" => Algorithm::SHA256,
-            "SHA-384" => Algorithm::SHA384,
-            "SHA-512" => Algorithm::SHA512,
-            _ => return Err(AppError::InvalidAlgorithmError(s.to_owned()))
+        Ok(match s.to_lowercase().as_str() {
+            "gnu" => InputFormat::Gnu,
+            "csv" => InputFormat::Csv,
+            _ => return Err(AppError::InvalidInputFormatError(s.to_owned()))
         })
     }
 }
+
+/// How verification results are printed.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ReportFormat {
+    /// A line per file, and the summary on stderr.
+    #[default]
+    Text,
+    /// A single JSON object once done, with the files by outcome and the summary.
+    Json,
+}
+
+impl Display for ReportFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReportFormat::Text => "text",
+            ReportFormat::Json => "json",
+        })
+    }
+}
+
+impl FromStr for ReportFormat {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "text" => ReportFormat::Text,
+            "json" => ReportFormat::Json,
+            _ => return Err(AppError::InvalidOutputFormatError(s.to_owned()))
+        })
+    }
+}
+
+/// Separators of the paths written to checksum files.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum PathStyle {
+    /// Whatever the platform uses, `\` on Windows.
+    #[default]
+    Native,
+    /// Always `/`, so checksum files read the same everywhere.
+    Unix,
+}
+
+impl Display for PathStyle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PathStyle::Native => "native",
+            PathStyle::Unix => "unix",
+        })
+    }
+}
+
+impl FromStr for PathStyle {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "native" => PathStyle::Native,
+            "unix" => PathStyle::Unix,
+            _ => return Err(AppError::InvalidPathStyleError(s.to_owned()))
+        })
+    }
+}
+
+/// What the paths written to checksum files are relative to.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum PathMode {
+    /// As walked from the directory on the command line, without a leading `./`.
+    #[default]
+    AsGiven,
+    /// Relative to the directory on the command line, even an absolute one, so the checksum file
+    /// still works once the tree is somewhere else.
+    Relative,
+    /// Absolute, with links resolved.
+    Absolute,
+}
+
+impl Display for PathMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PathMode::AsGiven => "as-given",
+            PathMode::Relative => "relative",
+            PathMode::Absolute => "absolute",
+        })
+    }
+}
+
+impl FromStr for PathMode {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_lowercase().as_str() {
+            "as-given" => PathMode::AsGiven,
+            "relative" => PathMode::Relative,
+            "absolute" => PathMode::Absolute,
+            _ => return Err(AppError::InvalidPathModeError(s.to_owned()))
+        })
+    }
+}
+
+/// Key for HMAC, given in hex or read as raw bytes from a file with `@path`.
+#[derive(Clone)]
+pub struct HmacKey(Vec<u8>);
+
+impl HmacKey {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl Debug for HmacKey {
+    // Keep the key out of debug output.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("HmacKey(..)")
+    }
+}
+
+impl FromStr for HmacKey {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let key = match s.strip_prefix('@') {
+            Some(path) => fs::read(path).ok(),
+            None => decode_checksum(s, Some(Encoding::Hex)).ok(),
+        };
+        match key {
+            Some(key) if !key.is_empty() => Ok(Self(key)),
+            _ => Err(AppError::InvalidHmacKeyError(s.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use structopt::StructOpt;
+    use crate::cmd_line::{BufferSize, FileSize, GenerationOpt, ThreadNum};
+
+    #[test]
+    fn test_thread_num() {
+        assert_eq!(usize::from("4".parse::<ThreadNum>().unwrap()), 4);
+        assert_eq!(usize::from("0".parse::<ThreadNum>().unwrap()), usize::from(ThreadNum::default()));
+        assert!("-1".parse::<ThreadNum>().is_err());
+        let opts = GenerationOpt::from_iter(&["g", "-n", "0"]);
+        assert_eq!(usize::from(opts.num_threads), num_cpus::get_physical());
+    }
+
+    #[test]
+    fn test_buffer_size() {
+        assert_eq!("4096".parse::<BufferSize>().unwrap(), BufferSize(4096));
+        assert_eq!("64K".parse::<BufferSize>().unwrap(), BufferSize(64 * 1024));
+        assert_eq!("1m".parse::<BufferSize>().unwrap(), BufferSize(1024 * 1024));
+        assert_eq!("1MiB".parse::<BufferSize>().unwrap(), BufferSize(1024 * 1024));
+        assert_eq!("2GB".parse::<BufferSize>().unwrap(), BufferSize(2 << 30));
+        assert!("0".parse::<BufferSize>().is_err());
+        assert_eq!("0".parse::<FileSize>().unwrap(), FileSize(0));
+        assert_eq!(u64::from("5G".parse::<FileSize>().unwrap()), 5 << 30);
+        assert!("1T".parse::<FileSize>().is_err());
+        assert!("1T".parse::<BufferSize>().is_err());
+        assert!("K".parse::<BufferSize>().is_err());
+        assert_eq!(BufferSize::default().to_string().parse::<BufferSize>().unwrap(), BufferSize::default());
+    }
+}
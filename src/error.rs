@@ -6,15 +6,69 @@ pub enum AppError {
     #[error("Invalid algorithm '{0}'.")]
     InvalidAlgorithmError(String),
 
+    #[error("Invalid output format '{0}'.")]
+    InvalidOutputFormatError(String),
+
+    #[error("Invalid input format '{0}'.")]
+    InvalidInputFormatError(String),
+
+    #[error("Invalid path style '{0}', expecting unix or native.")]
+    InvalidPathStyleError(String),
+
+    #[error("Invalid path mode '{0}', expecting relative, absolute or as-given.")]
+    InvalidPathModeError(String),
+
+    #[error("Invalid encoding '{0}'.")]
+    InvalidEncodingError(String),
+
+    #[error("Invalid template '{0}', supported placeholders are {{hash}}, {{algorithm}}, {{path}}, {{size}} and {{mtime}}.")]
+    InvalidTemplateError(String),
+
+    #[error("Invalid size '{0}', expecting a number of bytes with an optional K, M or G suffix.")]
+    InvalidSizeError(String),
+
+    #[error("Invalid option, {0}.")]
+    InvalidOptionError(String),
+
+    #[error("Invalid HMAC key '{0}', expecting hex digits or '@file'.")]
+    InvalidHmacKeyError(String),
+
+    #[error("Algorithm '{0}' cannot be used with an HMAC key.")]
+    UnkeyableAlgorithmError(String),
+
     #[error("'{0}' is inaccessible or not a file.")]
     InvalidFileError(PathBuf),
 
-    #[error("Cannot guess algorithm with {0} bytes hash value.")]
+    #[error("Cannot read '{0}', {1}.")]
+    ReadError(PathBuf, String),
+
+    #[error("Cannot read '{0}', permission denied.")]
+    PermissionDeniedError(PathBuf),
+
+    #[error("Cannot guess algorithm with {0} bytes hash value, use '-a' to specify it.")]
     UnknownAlgorithmError(usize),
 
+    #[error("Hash values of {0} bytes could be any of {1}, use '-a' or an '# algorithm:' header to specify it.")]
+    AmbiguousAlgorithmError(usize, String),
+
+    #[error("{0} checksums have {1} bytes, this one has {2}.")]
+    DigestLengthError(String, usize, usize),
+
     #[error("Hash value '{0}' is invalid.")]
     InvalidHashValue(String),
 
+    #[error("Invalid checksum line '{0}'.")]
+    InvalidLineError(String),
+
+    #[error("The checksum file is encoded in UTF-16, convert it to UTF-8 first.")]
+    Utf16Error,
+
+    #[error("{0} error(s) occurred.")]
+    RunError(usize),
+
+    #[error("Interrupted, {0} file(s) written before stopping.")]
+    InterruptedError(usize),
+
     #[error("Unknown error.")]
     UnknownError,
 }
@@ -0,0 +1,47 @@
+//! HMAC wrapped in the `digest` traits, so a keyed hash can be boxed as `DynDigest` like any other.
+
+// digest 0.9 is built on generic-array 0.14, whose latest releases deprecate `GenericArray`.
+#![allow(deprecated)]
+
+use digest::generic_array::GenericArray;
+use digest::{BlockInput, FixedOutput, FixedOutputDirty, Reset, Update};
+use hmac::{Hmac, Mac, NewMac};
+
+#[derive(Clone)]
+pub struct Keyed<D>(Hmac<D>)
+    where D: Update + BlockInput + FixedOutput + Reset + Default + Clone;
+
+impl<D> Keyed<D>
+    where D: Update + BlockInput + FixedOutput + Reset + Default + Clone
+{
+    pub fn new(key: &[u8]) -> Self {
+        // HMAC takes keys of any length, longer ones are hashed first.
+        Self(Hmac::new_from_slice(key).expect("Internal error."))
+    }
+}
+
+impl<D> Update for Keyed<D>
+    where D: Update + BlockInput + FixedOutput + Reset + Default + Clone
+{
+    fn update(&mut self, data: impl AsRef<[u8]>) {
+        Mac::update(&mut self.0, data.as_ref());
+    }
+}
+
+impl<D> FixedOutputDirty for Keyed<D>
+    where D: Update + BlockInput + FixedOutput + Reset + Default + Clone
+{
+    type OutputSize = D::OutputSize;
+
+    fn finalize_into_dirty(&mut self, out: &mut GenericArray<u8, D::OutputSize>) {
+        out.copy_from_slice(&self.0.finalize_reset().into_bytes());
+    }
+}
+
+impl<D> Reset for Keyed<D>
+    where D: Update + BlockInput + FixedOutput + Reset + Default + Clone
+{
+    fn reset(&mut self) {
+        Mac::reset(&mut self.0);
+    }
+}
@@ -0,0 +1,22 @@
+//! Checksum calculation and verification used by the `chksum` tool.
+//!
+//! ```
+//! use chksum::{calculate_checksum_bytes, encode_checksum, Algorithm, Encoding};
+//!
+//! let checksum = calculate_checksum_bytes(b"abcdABCD1234", Algorithm::MD5);
+//! assert_eq!(encode_checksum(&checksum, Encoding::Hex), "bb057481a1b7abc93ad5d70d52e3a55f");
+//! ```
+
+mod checksum;
+mod error;
+mod keyed;
+mod non_crypto;
+mod walk;
+
+pub use crate::checksum::{
+    ALGORITHMS, DEFAULT_BUFFER_SIZE, Algorithm, AlgorithmInfo, ByteRange, Encoding, calculate_checksum, calculate_checksum_bytes, calculate_checksums,
+    calculate_decompressed_checksums, calculate_range_checksums, decode_checksum, encode_checksum, guess_algorithm, likely_algorithm, verify_checksum, verify_range_checksum,
+    verify_size,
+};
+pub use crate::error::AppError;
+pub use crate::walk::{FileChecksums, ManifestOptions, WalkEvent, generate_manifest, walk_checksums, walk_files};
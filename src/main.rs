@@ -1,197 +1,3171 @@
-mod error;
-mod checksum;
 mod cmd_line;
+mod manifest;
+mod template;
 
 use std::fs::{OpenOptions, File};
-use std::io::{Write, BufRead, BufReader, Read};
-use std::path::{PathBuf, Path};
+use std::io::{self, BufWriter, IsTerminal, Write, Read};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fmt::{self, Display, Formatter};
+use std::path::{MAIN_SEPARATOR, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::channel;
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
-use itertools::{join, Itertools};
+use globset::GlobSetBuilder;
+use itertools::{EitherOrBoth, Itertools};
+use serde::Serialize;
+use indicatif::{HumanBytes, HumanCount, ProgressBar, ProgressStyle};
 use structopt::StructOpt;
+use structopt::clap::Shell;
+use walkdir::WalkDir;
 use threadpool::ThreadPool;
-use walkdir::{WalkDir, DirEntry};
 
-use crate::checksum::{calculate_checksum, verify_checksum};
-use crate::cmd_line::{AppArgs, Commands, GenerationOpt, VerificationOpt};
-use crate::error::AppError;
+use chksum::{
+    ALGORITHMS, AppError, Algorithm, ByteRange, Encoding, FileChecksums, ManifestOptions, WalkEvent, decode_checksum, encode_checksum,
+    guess_algorithm, likely_algorithm, verify_checksum, verify_range_checksum, verify_size, walk_checksums, walk_files,
+};
 
-fn output_checksum(entry: DirEntry, opts: &GenerationOpt) -> Result<(PathBuf, Vec<u8>)> {
-    let path = entry.path();
-    if path.is_dir() || !path.is_file() {
-        return Err(AppError::InvalidFileError(path.to_path_buf()).into());
+use crate::cmd_line::{
+    AppArgs, CheckOpt, Commands, CompareOpt, DiffOpt, DuplicateOpt, GenerationOpt, HmacKey, InputFormat, OutputFormat, PathMode, PathStyle, ReportFormat,
+    VerificationOpt,
+};
+use crate::manifest::{
+    Entries, Entry, Parser, PendingFile, Record, Writer, read_csv_entries, read_index, read_records, write_index,
+};
+
+/// Exit status when checksums don't match, or files are missing or unexpected.
+const EXIT_MISMATCH: i32 = 1;
+/// Exit status when something couldn't be done at all, e.g. files couldn't be read or the
+/// options are invalid.
+const EXIT_ERROR: i32 = 2;
+/// Exit status when generation is stopped with Ctrl-C, as shells report a process killed by SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// How a file compares to its entry in the checksum file.
+#[derive(Clone, Debug, PartialEq)]
+enum Verdict {
+    Ok,
+    Failed,
+    /// Doesn't match the algorithm guessed from its length, but could be a checksum of any of the
+    /// others named.
+    Ambiguous(Algorithm, String),
+    /// The size is off, so the content wasn't even read.
+    SizeMismatch,
+    Missing,
+    /// The file couldn't be read, for the given reason.
+    Error(String),
+}
+
+/// Failing to read the file is a verdict on it, errors are left for problems with the entry itself.
+fn verify_entry(file: &Path, entry: &Entry, algorithm: Option<Algorithm>, encoding: Option<Encoding>, key: Option<&[u8]>, mixed: bool) -> Result<Verdict> {
+    compare_entry(file, entry, algorithm, encoding, key, mixed).or_else(|e| match e.downcast_ref::<io::Error>() {
+        Some(e) if e.kind() == io::ErrorKind::NotFound => Ok(Verdict::Missing),
+        Some(e) => Ok(Verdict::Error(e.to_string())),
+        None => Err(e),
+    })
+}
+
+/// The algorithm an entry is verified with, the same way `compare_entry` picks it. `None` when its
+/// checksum fits no algorithm.
+fn assumed_algorithm(entry: &Entry, algorithm: Option<Algorithm>, encoding: Option<Encoding>, mixed: bool) -> Option<Algorithm> {
+    let length = decode_checksum(&entry.checksum, encoding).ok()?.len();
+    match entry.algorithm.or(algorithm) {
+        Some(algorithm) if mixed && algorithm.digest_size() != length => likely_algorithm(length),
+        Some(algorithm) => Some(algorithm),
+        None => likely_algorithm(length),
+    }
+}
+
+/// With `mixed`, an entry whose checksum doesn't fit the algorithm is verified with one guessed from
+/// its length instead.
+fn compare_entry(file: &Path, entry: &Entry, algorithm: Option<Algorithm>, encoding: Option<Encoding>, key: Option<&[u8]>, mixed: bool) -> Result<Verdict> {
+    // Size mismatch is a cheap way to detect modification without reading the content.
+    if let Some(size) = entry.size {
+        if !verify_size(file, size)? {
+            return Ok(Verdict::SizeMismatch);
+        }
+    }
+    let given = entry.algorithm.or(algorithm);
+    let (matched, guessed) = match verify_checksum(file, &entry.checksum, given, encoding, key) {
+        Err(e) if mixed && matches!(e.downcast_ref::<AppError>(), Some(AppError::DigestLengthError(..))) => {
+            (verify_checksum(file, &entry.checksum, None, encoding, key)?.1, true)
+        }
+        result => (result?.1, given.is_none()),
+    };
+    if matched {
+        return Ok(Verdict::Ok);
     }
-    let c = calculate_checksum(path, opts.algorithm)?;
-    Ok((path.to_owned(), c))
+    // Without a header or '-a', the checksum may as well be one of another algorithm of its length.
+    let length = decode_checksum(&entry.checksum, encoding)?.len();
+    Ok(match likely_algorithm(length).filter(|_| guessed).and_then(|a| same_length(a).map(|others| (a, others))) {
+        Some((algorithm, others)) => Verdict::Ambiguous(algorithm, others),
+        None => Verdict::Failed,
+    })
 }
 
-struct Exclusion {
-    e: Vec<PathBuf>,
+fn progress_bar(opts: &GenerationOpt) -> ProgressBar {
+    // Only draw on an interactive stderr, so piped output stays clean, and not when the manifest
+    // itself is printed to the terminal.
+    let to_terminal = opts.checksum_file == Path::new("-") && std::io::stdout().is_terminal();
+    if opts.no_progress || to_terminal || !std::io::stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+    let progress = ProgressBar::new(0);
+    progress.set_style(ProgressStyle::default_bar().template("{bar:40} {pos}/{len} files ({elapsed})").expect("Internal error."));
+    progress
 }
 
-impl Exclusion {
-    fn new(excludes: &Vec<PathBuf>, checksum_file: &PathBuf) -> Self {
-        Self {
-            e: excludes.iter().filter_map(|p| {
-                if p.to_string_lossy() == "-" {
-                    if checksum_file.to_string_lossy() == "-" {
-                        None
-                    } else {
-                        checksum_file.canonicalize().ok()
-                    }
-                } else {
-                    p.canonicalize().ok()
+/// `appending` is set when the output already has records, which then get no header in front.
+fn write_records(output: &mut Writer, results: &[Record], opts: &GenerationOpt, appending: bool) -> Result<()> {
+    // The header only goes into GNU style files, the other formats name their algorithms already.
+    if opts.header && !appending && opts.format_template.is_none() && opts.output_format == OutputFormat::Gnu {
+        output.write_header(&opts.algorithm, opts.hmac_key.is_some())?;
+    }
+    match (&opts.format_template, opts.output_format) {
+        (Some(template), _) => {
+            for (idx, algorithm) in opts.algorithm.iter().enumerate() {
+                for e in results.iter() {
+                    output.write_record(&template.render(&e.path, &e.checksums[idx], *algorithm, e.size, e.modified))?;
+                }
+            }
+        }
+        (None, OutputFormat::Gnu) if opts.with_size => {
+            if !appending {
+                output.write_size_header()?;
+            }
+            for e in results.iter() {
+                output.write_sized_gnu_entry(&e.path, &e.checksums[0], e.size)?;
+            }
+        }
+        (None, OutputFormat::Gnu) if opts.tag_algorithm => {
+            if !appending {
+                output.write_algorithm_column_header()?;
+            }
+            for (idx, algorithm) in opts.algorithm.iter().enumerate() {
+                for e in results.iter() {
+                    output.write_algorithm_gnu_entry(&e.path, &e.checksums[idx], *algorithm)?;
+                }
+            }
+        }
+        (None, OutputFormat::Gnu) if opts.algorithm.len() == 1 => {
+            for e in results.iter() {
+                output.write_gnu_entry(&e.path, &e.checksums[0])?;
+            }
+        }
+        (None, OutputFormat::Gnu) => {
+            // Multiple algorithms are written as tagged lines, grouped per algorithm.
+            for (idx, algorithm) in opts.algorithm.iter().enumerate() {
+                for e in results.iter() {
+                    output.write_tagged_entry(&e.path, &e.checksums[idx], *algorithm)?;
                 }
-            }).unique().collect()
+            }
+        }
+        (None, OutputFormat::Csv) => output.write_csv_entries(results, &opts.algorithm)?,
+        (None, OutputFormat::Json) => output.write_json_entries(results, &opts.algorithm, false)?,
+        (None, OutputFormat::Jsonl) => output.write_json_entries(results, &opts.algorithm, true)?,
+        (None, OutputFormat::Hashdeep) => {
+            output.write_hashdeep_header(&opts.algorithm)?;
+            for e in results.iter() {
+                output.write_hashdeep_entry(&e.path, &e.checksums, e.size)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A checksum file opened by `create_output`.
+struct Output {
+    writer: Writer,
+    /// Set when the file already has records, which then get no header in front.
+    appending: bool,
+    /// Set when writing under a temporary name, to replace the checksum file once complete.
+    pending: Option<PendingFile>,
+}
+
+impl Output {
+    fn commit(mut self) -> Result<()> {
+        self.writer.flush()?;
+        match self.pending {
+            Some(pending) => pending.commit(),
+            None => Ok(()),
         }
     }
+}
 
-    fn is_excluded(&self, path: &Path) -> bool {
-        let c = match path.canonicalize() {
-            Ok(p) => p,
-            Err(e) => {
-                eprintln!("{}", e);
-                return false;
+fn create_output(path: &Path, opts: &GenerationOpt) -> Result<Output> {
+    let mut appending = false;
+    let mut pending = None;
+    let output: Box<dyn Write> = if path == Path::new("-") {
+        Box::new(std::io::stdout())
+    } else if opts.append {
+        appending = path.metadata().is_ok_and(|m| m.len() > 0);
+        Box::new(BufWriter::new(OpenOptions::new().create(true).append(true).open(path)?))
+    } else {
+        // The previous checksum file stays in place until the new one is complete, so an
+        // interrupted run doesn't leave it truncated.
+        let (file, output) = PendingFile::create(path)?;
+        pending = Some(file);
+        Box::new(BufWriter::new(output))
+    };
+    Ok(Output { writer: Writer::new(output, opts.zero, opts.binary), appending, pending })
+}
+
+/// What `--resume` keeps of an existing checksum file.
+#[derive(Default)]
+struct Resumed {
+    /// Lines carried over as they are, all but those of files changed since.
+    lines: Vec<String>,
+    /// Files listed and unchanged since, which aren't hashed again.
+    done: Vec<PathBuf>,
+}
+
+fn read_resumed(path: &Path, opts: &GenerationOpt) -> Result<Resumed> {
+    let input = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Resumed::default()),
+        Err(e) => return Err(e.into()),
+    };
+    let written = input.metadata()?.modified()?;
+    let mut parser = Parser::new(None, opts.zero);
+    let mut resumed = Resumed::default();
+    for line in read_records(Box::new(input), opts.zero) {
+        let line = line?;
+        // Comments and lines that can't be parsed are kept, as the files they list are unknown.
+        if let Ok(Some(entry)) = parser.parse_line(&line) {
+            // Files modified after the checksum file was written, or gone since, are dropped and
+            // hashed again if still there.
+            if entry.path.metadata().and_then(|m| m.modified()).map_or(true, |t| t > written) {
+                continue;
             }
-        };
-        for p in self.e.iter() {
-            if p == &c { return true; }
+            resumed.done.push(entry.path);
         }
-        false
+        resumed.lines.push(line);
     }
+    Ok(resumed)
 }
 
-fn generate_checksums(opts: &GenerationOpt) -> Result<bool> {
-    let pool = ThreadPool::new(opts.num_threads.into());
-    let dot_prefix = format!(".{}", std::path::MAIN_SEPARATOR);
-    let mut all_succeeded: bool = true;
+/// Where `--update` keeps the sizes and modification times, next to the checksum file, e.g.
+/// `checksums.txt.index`.
+fn index_path(checksum_file: &Path) -> PathBuf {
+    let mut path = checksum_file.to_path_buf().into_os_string();
+    path.push(".index");
+    PathBuf::from(path)
+}
+
+/// Opens the list of files to hash given to `--files-from`, stdin with `-`.
+fn open_file_list(path: &Path) -> Result<Box<dyn Read>> {
+    Ok(if path == Path::new("-") {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path)?)
+    })
+}
+
+/// Reads the files to hash, one per line or NUL-terminated with `zero`. Empty lines are skipped.
+fn read_file_list(input: Box<dyn Read>, zero: bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for record in read_records(input, zero) {
+        let record = record?;
+        if !record.is_empty() {
+            files.push(PathBuf::from(record));
+        }
+    }
+    Ok(files)
+}
+
+/// Drops the leading `.` of paths walked from the current directory, whichever separator follows it.
+fn strip_dot_prefix(path: &Path) -> &Path {
+    path.strip_prefix(".").unwrap_or(path)
+}
+
+/// The path written for a file found under `root`, as `mode` says.
+fn output_path(path: &Path, root: &Path, mode: PathMode) -> PathBuf {
+    match mode {
+        PathMode::AsGiven => strip_dot_prefix(path).to_owned(),
+        PathMode::Relative => {
+            let path = strip_dot_prefix(path);
+            match path.strip_prefix(strip_dot_prefix(root)) {
+                // A file given in place of the directory is left with its name.
+                Ok(relative) if relative.as_os_str().is_empty() => path.file_name().map_or_else(|| path.to_owned(), PathBuf::from),
+                Ok(relative) => relative.to_owned(),
+                // Listed by `--files-from`, or outside of `--strip-prefix`.
+                Err(_) => path.to_owned(),
+            }
+        }
+        // The file was just read, it can only fail to resolve if it went away meanwhile.
+        PathMode::Absolute => path.canonicalize().unwrap_or_else(|_| path.to_owned()),
+    }
+}
+
+/// The path with `/` separators, for `--path-style unix`.
+fn unix_path(path: &Path) -> PathBuf {
+    match MAIN_SEPARATOR {
+        '/' => path.to_owned(),
+        _ => PathBuf::from(path.to_string_lossy().replace(MAIN_SEPARATOR, "/")),
+    }
+}
+
+/// Reads the index of an earlier `--update` run, a missing or unreadable one means hashing
+/// everything again.
+fn load_index(path: &Path, algorithms: &[Algorithm]) -> HashMap<PathBuf, FileChecksums> {
+    let index = match File::open(path) {
+        Ok(file) => read_index(file, algorithms),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return HashMap::new(),
+        Err(e) => Err(e.into()),
+    };
+    index.unwrap_or_else(|e| {
+        eprintln!("chksum: {}: {}, hashing all files", path.display(), e);
+        HashMap::new()
+    })
+}
+
+/// Writes a manifest named after the checksum file into each directory holding files, listing
+/// them by their bare file names.
+fn write_per_directory(results: Vec<Record>, manifest_name: &OsStr, opts: &GenerationOpt) -> Result<()> {
+    // Files of a directory aren't necessarily adjacent in path order, `a/b/c` sorts between `a/a` and `a/c`.
+    let mut directories: BTreeMap<PathBuf, Vec<Record>> = BTreeMap::new();
+    for r in results {
+        let dir = r.path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let path = r.path.file_name().map(PathBuf::from).unwrap_or_default();
+        directories.entry(dir).or_default().push(Record { path, ..r });
+    }
+    for (dir, records) in directories.iter() {
+        let mut output = create_output(&dir.join(manifest_name), opts)?;
+        write_records(&mut output.writer, records, opts, output.appending)?;
+        output.commit()?;
+    }
+    Ok(())
+}
+
+/// Generates checksums, stopping on Ctrl-C, and reports the work done when asked or on a terminal.
+fn run_generation(opts: &GenerationOpt) -> Result<bool> {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+    // A second Ctrl-C doesn't wait for the files being hashed.
+    ctrlc::set_handler(move || if flag.swap(true, Ordering::Relaxed) {
+        std::process::exit(EXIT_INTERRUPTED);
+    })?;
+    let mut stats = Stats::default();
+    let result = generate_checksums_until(opts, &interrupted, &mut stats);
+    if opts.stats || io::stderr().is_terminal() {
+        eprintln!("{}", stats);
+    }
+    result
+}
+
+/// Generates checksums until `interrupted` is set, after which the files already being hashed are
+/// finished and written, so the checksum file only ever holds whole lines. What was hashed is
+/// counted in `stats`.
+fn generate_checksums_until(opts: &GenerationOpt, interrupted: &Arc<AtomicBool>, stats: &mut Stats) -> Result<bool> {
+    let started = Instant::now();
+    let completed;
+    let mut errors: Vec<String> = Vec::new();
+    // Files and directories that couldn't be read for lack of permissions, only errors with `--strict`.
+    let mut skipped: Vec<String> = Vec::new();
     {
-        let (tx, rx) = channel();
-        let mut count: usize = 0;
-        let exclusion = Exclusion::new(&opts.exclude, &opts.checksum_file);
-        for entry in opts.directory.iter().map(|d| WalkDir::new(d).follow_links(true).same_file_system(true)).flatten() {
-            match entry {
-                Ok(e) => {
-                    if e.path().is_dir() || !e.path().is_file() || exclusion.is_excluded(e.path()) {
-                        continue;
-                    }
-                    let tx = tx.clone();
-                    let opts = opts.clone();
-                    pool.execute(move || {
-                        tx.send(output_checksum(e, &opts)).expect("Internal error.");
-                    });
-                }
-                Err(e) => {
-                    eprintln!("{}", e);
-                }
-            };
-            count += 1;
+        let progress = progress_bar(opts);
+        if opts.append && opts.checksum_file == Path::new("-") {
+            return Err(AppError::InvalidOptionError("--append can't be used when writing to stdout".to_owned()).into());
         }
+        // Headers in the middle of a CSV or hashdeep file would make it unreadable.
+        if opts.append && opts.format_template.is_none() && opts.output_format != OutputFormat::Gnu {
+            return Err(AppError::InvalidOptionError("--append only works with the gnu output format".to_owned()).into());
+        }
+        if opts.resume && (opts.update || opts.append || opts.per_directory || opts.checksum_file == Path::new("-")) {
+            return Err(AppError::InvalidOptionError("--resume needs a single checksum file to add to".to_owned()).into());
+        }
+        // Only GNU style lines can be read back.
+        if opts.resume && (opts.format_template.is_some() || opts.output_format != OutputFormat::Gnu) {
+            return Err(AppError::InvalidOptionError("--resume only works with the gnu output format".to_owned()).into());
+        }
+        // Tagged lines have no room for the size, and the other formats have their own column.
+        if opts.with_size && (opts.algorithm.len() > 1 || opts.format_template.is_some() || opts.output_format != OutputFormat::Gnu) {
+            return Err(AppError::InvalidOptionError("--with-size only works with the gnu output format and a single algorithm".to_owned()).into());
+        }
+        if opts.tag_algorithm && (opts.format_template.is_some() || opts.output_format != OutputFormat::Gnu) {
+            return Err(AppError::InvalidOptionError("--tag-algorithm only works with the gnu output format".to_owned()).into());
+        }
+        if opts.decompress && (opts.offset > 0 || opts.length.is_some()) {
+            return Err(AppError::InvalidOptionError("--decompress can't be used with --offset or --length".to_owned()).into());
+        }
+        if opts.max_depth.is_some_and(|max| opts.min_depth > max) {
+            return Err(AppError::InvalidOptionError("--min-depth can't be larger than --max-depth".to_owned()).into());
+        }
+        if opts.min_size.zip(opts.max_size).is_some_and(|(min, max)| min > max) {
+            return Err(AppError::InvalidOptionError("--min-size can't be larger than --max-size".to_owned()).into());
+        }
+        // Paths relative to one of several directories could be any of them, and neither
+        // `--resume` nor `--per-directory` could find the files listed.
+        let roots = opts.roots();
+        if opts.path_mode == PathMode::Relative && (roots.len() > 1 || opts.resume || opts.per_directory) {
+            return Err(AppError::InvalidOptionError("--path-mode relative needs a single directory, without --resume or --per-directory".to_owned()).into());
+        }
+        if opts.strip_prefix.is_some() && (opts.resume || opts.per_directory) {
+            return Err(AppError::InvalidOptionError("--strip-prefix can't be used with --resume or --per-directory".to_owned()).into());
+        }
+        if opts.update && (opts.append || opts.per_directory || opts.checksum_file == Path::new("-")) {
+            return Err(AppError::InvalidOptionError("--update needs a single checksum file to rewrite".to_owned()).into());
+        }
+        let manifest_name = match (opts.per_directory, opts.checksum_file.file_name()) {
+            (true, Some(name)) if opts.checksum_file != Path::new("-") => Some(name.to_owned()),
+            (true, _) => return Err(AppError::InvalidOptionError("--per-directory needs a checksum file name".to_owned()).into()),
+            (false, _) => None,
+        };
+        let index_path = opts.update.then(|| index_path(&opts.checksum_file));
 
+        let resumed = match opts.resume {
+            true => read_resumed(&opts.checksum_file, opts)?,
+            false => Resumed::default(),
+        };
 
-        let mut output: Box<dyn Write> = if opts.checksum_file == PathBuf::from("-") {
-            Box::new(std::io::stdout())
-        } else {
-            Box::new(OpenOptions::new().create(true).write(true).truncate(true).open(&opts.checksum_file)?)
+        // Opened before walking, so an unwritable checksum file fails early.
+        let output = match manifest_name {
+            None => Some(create_output(&opts.checksum_file, opts)?),
+            Some(_) => None,
         };
 
-        let mut results: Vec<(PathBuf, String)> = Vec::new();
-        for _ in 0..count {
-            match rx.iter().next().ok_or(AppError::UnknownError)? {
-                Ok((path, checksum)) => {
-                    let path = path.strip_prefix(&dot_prefix).unwrap_or(&path);
-                    let checksum_str = join(checksum.into_iter().map(|b| format!("{:02x}", b)), "");
-                    results.push((path.to_owned(), checksum_str));
-                }
-                Err(e) => {
-                    eprintln!("{}", e);
-                    all_succeeded = false
-                }
+        // The checksum file itself is always excluded, `-` in the exclusion list is kept for compatibility.
+        let exclude = opts.exclude.iter()
+            .chain(Some(&opts.checksum_file))
+            .chain(&index_path)
+            .chain(&resumed.done)
+            .filter(|p| p.as_path() != Path::new("-"))
+            .cloned()
+            .chain(output.iter().filter_map(|o| o.pending.as_ref()).map(|p| p.temp_path().to_owned()))
+            .collect();
+        let manifest_opts = ManifestOptions {
+            num_threads: opts.num_threads.into(),
+            io_concurrency: opts.io_concurrency,
+            exclude,
+            // Per-directory manifests, old and new, must not end up in each other.
+            exclude_file_names: manifest_name.iter().cloned().collect(),
+            include_globs: opts.include_globs.clone(),
+            exclude_globs: opts.exclude_globs.clone(),
+            exclude_extensions: opts.exclude_extensions.iter().map(|e| e.trim_start_matches('.').to_lowercase()).collect(),
+            gitignore: opts.gitignore,
+            // Links are followed and file system boundaries respected unless asked otherwise, the
+            // last of a pair of opposite flags wins.
+            follow_links: opts.follow_symlinks || !opts.no_follow_symlinks,
+            same_file_system: opts.one_file_system || !opts.cross_file_system,
+            skip_hidden: opts.no_hidden,
+            // Only the files directly inside the directories, whatever `--max-depth` says. As above,
+            // the last of the pair wins.
+            max_depth: if opts.no_recursive && !opts.recursive { Some(1) } else { opts.max_depth },
+            min_depth: opts.min_depth,
+            decompress: opts.decompress,
+            range: ByteRange { offset: opts.offset, length: opts.length },
+            buffer_size: opts.buffer_size.into(),
+            hmac_key: opts.hmac_key.as_ref().map(|k| k.as_bytes().to_vec()),
+            min_size: opts.min_size.map_or(0, u64::from),
+            max_size: opts.max_size.map(u64::from),
+            files: opts.files_from.as_deref().map(|p| read_file_list(open_file_list(p)?, opts.zero || opts.null)).transpose()?,
+            previous: index_path.as_ref().map(|p| load_index(p, &opts.algorithm)).unwrap_or_default(),
+            interrupted: Some(interrupted.clone()),
+        };
+
+        let mut results: Vec<Record> = Vec::new();
+        // Everything hashed or carried over, for the next `--update` run.
+        let mut files: Vec<FileChecksums> = Vec::new();
+        let mut add = |c: FileChecksums| {
+            let path = match &opts.strip_prefix {
+                Some(prefix) => output_path(&c.path, prefix, PathMode::Relative),
+                None => output_path(&c.path, &roots[0], opts.path_mode),
+            };
+            results.push(Record {
+                path: match opts.path_style {
+                    PathStyle::Native if !opts.slash => path,
+                    PathStyle::Native => unix_path(&path),
+                    PathStyle::Unix => unix_path(&path),
+                },
+                checksums: c.checksums.iter().map(|c| encode_checksum(c, opts.encoding)).collect(),
+                size: c.size,
+                modified: c.modified,
+            });
+            if opts.update {
+                files.push(c);
+            }
+        };
+        walk_checksums(&roots, &opts.algorithm, &manifest_opts, |event| match event {
+            WalkEvent::Queued(_) => progress.inc_length(1),
+            WalkEvent::Hashed(Ok(c)) => {
+                stats.files += 1;
+                stats.bytes += c.size;
+                add(c);
+                progress.inc(1);
+            }
+            WalkEvent::Unchanged(c) => add(c),
+            WalkEvent::Hashed(Err(e)) => {
+                if is_permission_denied(&e) { &mut skipped } else { &mut errors }.push(e.to_string());
+                progress.inc(1);
             }
+            WalkEvent::Error(e) => if is_permission_denied(&e) { &mut skipped } else { &mut errors }.push(e.to_string()),
+        })?;
+        stats.elapsed = started.elapsed();
+        // The bar must be gone before anything is written, as the output may go to the same terminal.
+        progress.finish_and_clear();
+        results.sort_by(|e1, e2| e1.path.partial_cmp(&e2.path).unwrap());
+        completed = results.len();
+        if let Some(mut output) = output {
+            for line in resumed.lines.iter() {
+                output.writer.write_record(line)?;
+            }
+            // Lines carried over include any header already.
+            let appending = output.appending || !resumed.lines.is_empty();
+            write_records(&mut output.writer, &results, opts, appending)?;
+            output.commit()?;
+        } else if let Some(name) = &manifest_name {
+            write_per_directory(results, name, opts)?;
         }
-        results.sort_by(|e1, e2| e1.0.partial_cmp(&e2.0).unwrap());
-        for e in results.into_iter() {
-            output.write(format!("{}  {}\n", e.1, e.0.display()).as_bytes())?;
+        if let Some(index_path) = &index_path {
+            let (pending, index) = PendingFile::create(index_path)?;
+            write_index(BufWriter::new(index), &files, &opts.algorithm)?;
+            pending.commit()?;
         }
     }
-    pool.join();
-    Ok(all_succeeded)
+    // Files that couldn't be read are left out, but the checksum file is still written.
+    if !skipped.is_empty() {
+        eprintln!("Skipped {} unreadable file(s):", skipped.len());
+        for e in skipped.iter() {
+            eprintln!("  {}", e);
+        }
+    }
+    print_errors(&errors);
+    if interrupted.load(Ordering::Relaxed) {
+        return Err(AppError::InterruptedError(completed).into());
+    }
+    let failures = if opts.strict { errors.len() + skipped.len() } else { errors.len() };
+    if failures > 0 {
+        return Err(AppError::RunError(failures).into());
+    }
+    Ok(true)
 }
 
-macro_rules! next_part {
-    ($parts:expr, $line:expr) => {
-         match $parts.next().ok_or(AppError::InvalidHashValue($line.to_string())) {
-                Ok(s) => s,
-                Err(e) => {
-                    eprintln!("{:?}", e);
-                    continue;
-                }
-            }.to_owned()
+/// Whether an error comes down to lacking permissions, for a file or for a directory being walked.
+fn is_permission_denied(e: &anyhow::Error) -> bool {
+    matches!(e.downcast_ref::<AppError>(), Some(AppError::PermissionDeniedError(_)))
+        || e.chain().any(|c| c.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied))
+}
+
+/// Lists the errors met during a run. They are collected rather than printed as they come, so
+/// they don't get lost among the results or garble the progress bar.
+fn print_errors(errors: &[String]) {
+    if !errors.is_empty() {
+        eprintln!("{} error(s):", errors.len());
+        for e in errors.iter() {
+            eprintln!("  {}", e);
+        }
     }
 }
 
-fn verify_checksums(opts: &VerificationOpt) -> Result<bool> {
+fn improperly_formatted(checksum_file: &Path, line: u64) -> String {
+    format!("chksum: {}: line {}: improperly formatted checksum line", checksum_file.display(), line)
+}
+
+fn read_entries(input: Box<dyn Read>, opts: &VerificationOpt) -> Result<Entries> {
+    parse_entries(input, opts.input_format, opts.algorithm, opts.zero)
+}
+
+fn parse_entries(input: Box<dyn Read>, format: InputFormat, algorithm: Option<Algorithm>, zero: bool) -> Result<Entries> {
+    Ok(match format {
+        InputFormat::Gnu => {
+            let mut parser = Parser::new(algorithm, zero);
+            Box::new(read_records(input, zero).zip(1..).map(move |(record, line)| {
+                (line, record.map_err(anyhow::Error::from).and_then(|r| parser.parse_line(&r)))
+            }))
+        }
+        InputFormat::Csv => read_csv_entries(input, algorithm, zero)?,
+    })
+}
+
+/// Outcomes of a verification, printed once done unless `--no-summary` is given.
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct Summary {
+    ok: usize,
+    failed: usize,
+    missing: usize,
+    unreadable: usize,
+    malformed: usize,
+    /// Entries for a path and algorithm listed before, failing only with `--strict`.
+    duplicates: usize,
+    /// Entries not selected by `--only`.
+    filtered: usize,
+    /// Files found by `--check-extra` but not listed.
+    extra: usize,
+    /// What went wrong other than mismatches, e.g. unreadable files.
+    errors: Vec<String>,
+    /// The files by outcome, only kept for `--format json`.
+    #[serde(skip)]
+    files: Outcomes,
+}
+
+/// Files verified, by outcome, as shown in the per-file lines.
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct Outcomes {
+    ok: Vec<PathBuf>,
+    failed: Vec<PathBuf>,
+    missing: Vec<PathBuf>,
+    extra: Vec<PathBuf>,
+}
+
+impl Summary {
+    fn add(&mut self, other: &Summary) {
+        self.ok += other.ok;
+        self.failed += other.failed;
+        self.missing += other.missing;
+        self.unreadable += other.unreadable;
+        self.malformed += other.malformed;
+        self.duplicates += other.duplicates;
+        self.filtered += other.filtered;
+        self.extra += other.extra;
+        self.errors.extend(other.errors.iter().cloned());
+        self.files.ok.extend(other.files.ok.iter().cloned());
+        self.files.failed.extend(other.files.failed.iter().cloned());
+        self.files.missing.extend(other.files.missing.iter().cloned());
+        self.files.extra.extend(other.files.extra.iter().cloned());
+    }
+
+    fn checked(&self) -> usize {
+        self.ok + self.failed + self.missing + self.unreadable
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} OK, {} FAILED, {} missing, {} unreadable, {} malformed line{}",
+               self.ok, self.failed, self.missing, self.unreadable, self.malformed, if self.malformed == 1 { "" } else { "s" })?;
+        if self.duplicates > 0 {
+            write!(f, ", {} duplicate{}", self.duplicates, if self.duplicates == 1 { "" } else { "s" })?;
+        }
+        if self.filtered > 0 {
+            write!(f, ", {} filtered out", self.filtered)?;
+        }
+        if self.extra > 0 {
+            write!(f, ", {} extra", self.extra)?;
+        }
+        Ok(())
+    }
+}
+
+/// Files hashed by a generation run, not counting those carried over by `--update`.
+#[derive(Debug, Default)]
+struct Stats {
+    files: u64,
+    bytes: u64,
+    elapsed: Duration,
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let seconds = self.elapsed.as_secs_f64();
+        write!(f, "Hashed {} files ({}) in {:.1}s", HumanCount(self.files), HumanBytes(self.bytes), seconds)?;
+        if seconds > 0.0 {
+            write!(f, " ({}/s)", HumanBytes((self.bytes as f64 / seconds) as u64))?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the file of an entry is, relative to `base_dir` if given. Paths written on Windows have `\`
+/// separators, which are valid in names elsewhere, so they are only taken as separators when no file
+/// has the name as written.
+fn resolve_entry_path(base_dir: Option<&Path>, path: &Path) -> PathBuf {
+    let join = |path: &Path| match base_dir {
+        Some(dir) => dir.join(path),
+        None => path.to_owned(),
+    };
+    let file = join(path);
+    if MAIN_SEPARATOR == '\\' || file.exists() {
+        return file;
+    }
+    match path.to_str() {
+        Some(s) if s.contains('\\') => {
+            let normalized = join(Path::new(&s.replace('\\', "/")));
+            if normalized.exists() { normalized } else { file }
+        }
+        _ => file,
+    }
+}
+
+/// Relative paths are relative to the directory containing the checksum file, unless `--base-dir`
+/// says otherwise.
+fn base_dir(opts: &VerificationOpt, checksum_file: &Path) -> Option<PathBuf> {
+    if opts.base_dir.is_some() {
+        opts.base_dir.clone()
+    } else if checksum_file == Path::new("-") {
+        None
+    } else {
+        checksum_file.parent().map(Path::to_path_buf)
+    }
+}
+
+/// Verifies the files listed in `checksum_file`, reporting them prefixed with `display_dir` if given
+/// and adding the outcomes to `summary`.
+fn verify_checksum_file(opts: &VerificationOpt, checksum_file: &Path, display_dir: Option<&Path>, summary: &mut Summary) -> Result<bool> {
+    verify_checksum_file_with(opts, checksum_file, display_dir, summary, Arc::new(verify_entry))
+}
+
+/// Checks a file against its entry, with the algorithm, encoding, key and `--mixed` to use.
+type EntryVerifier = dyn Fn(&Path, &Entry, Option<Algorithm>, Option<Encoding>, Option<&[u8]>, bool) -> Result<Verdict> + Send + Sync;
+
+/// Like `verify_checksum_file`, with each entry checked by `verify` on the thread pool.
+fn verify_checksum_file_with(opts: &VerificationOpt, checksum_file: &Path, display_dir: Option<&Path>, summary: &mut Summary, verify: Arc<EntryVerifier>) -> Result<bool> {
     let pool = ThreadPool::new(opts.num_threads.into());
     let mut all_succeeded: bool = true;
     {
-        let input: Box<dyn Read> = if opts.checksum_file == PathBuf::from("-") {
+        let input: Box<dyn Read> = if checksum_file == Path::new("-") {
             Box::new(std::io::stdin())
         } else {
-            Box::new(File::open(&opts.checksum_file)?)
+            Box::new(File::open(checksum_file)?)
         };
+        let base_dir = base_dir(opts, checksum_file);
         let (tx, rx) = channel();
-        let mut count: usize = 0;
-        for line in BufReader::new(input).lines() {
-            let line = line?;
-            let mut parts = line.split_whitespace();
-
-            let checksum = next_part!(parts, line);
-            let path = PathBuf::from(next_part!(parts, line));
+        // Set by the first failing entry with `--fail-fast`, nothing else is read after that.
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut queued = false;
+        let mut counts = Summary::default();
+        let mut only = GlobSetBuilder::new();
+        for glob in opts.only.iter() {
+            only.add(glob.clone());
+        }
+        let only = only.build()?;
+        // The line each path was first listed on, by algorithm as files with several list each path once per algorithm.
+        let mut seen: HashMap<(PathBuf, Option<Algorithm>), u64> = HashMap::new();
+        // Per-file lines are left out of the JSON report, which lists the files instead.
+        let json = opts.format == ReportFormat::Json;
+        let warn = |line: u64, e: &anyhow::Error| if opts.warn {
+            eprintln!("{}", improperly_formatted(checksum_file, line));
+        } else {
+            eprintln!("{}", e);
+        };
+        for (line, entry) in read_entries(input, opts)? {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let entry = match entry {
+                Ok(Some(entry)) => entry,
+                Ok(None) => continue,
+                // Malformed records are skipped, but failing to read the input is fatal.
+                Err(e) if e.is::<AppError>() => {
+                    warn(line, &e);
+                    counts.malformed += 1;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            // Patterns match the path as written, files not selected aren't even looked for.
+            if !opts.only.is_empty() && !only.is_match(&entry.path) {
+                counts.filtered += 1;
+                continue;
+            }
+            // Such as from concatenated or badly merged checksum files, both entries are still verified.
+            let first = *seen.entry((entry.path.clone(), entry.algorithm)).or_insert(line);
+            if first != line {
+                eprintln!("chksum: {}: line {}: '{}' is already listed on line {}", checksum_file.display(), line, entry.path.display(), first);
+                counts.duplicates += 1;
+            }
+            let file = resolve_entry_path(base_dir.as_deref(), &entry.path);
+            if opts.ignore_missing && !file.exists() {
+                if json {
+                    counts.files.missing.push(display_dir.map_or_else(|| entry.path.clone(), |d| d.join(&entry.path)));
+                }
+                counts.missing += 1;
+                continue;
+            }
             let algorithm = opts.algorithm;
+            let encoding = opts.encoding;
+            let key = opts.hmac_key.clone();
+            let mixed = opts.mixed;
+            let verbose = opts.verbose;
+            let fail_fast = opts.fail_fast;
+            let stop = stop.clone();
             let tx = tx.clone();
+            let verify = verify.clone();
 
             pool.execute(move || {
-                tx.send(verify_checksum(&path, &checksum, algorithm)).expect("Internal error.");
+                // Jobs still queued when a failure is seen are dropped without reading their file.
+                if stop.load(Ordering::Relaxed) {
+                    return;
+                }
+                // Only worked out for showing, verifying picks it by itself.
+                let assumed = if verbose { assumed_algorithm(&entry, algorithm, encoding, mixed) } else { None };
+                let result = verify(&file, &entry, algorithm, encoding, key.as_ref().map(HmacKey::as_bytes), mixed)
+                    .map(|verdict| (entry.path, verdict, assumed));
+                // Undecodable checksums are malformed lines, which only fail with `--strict`.
+                let failed = match &result {
+                    Ok((_, verdict, _)) => *verdict != Verdict::Ok,
+                    Err(e) => !matches!(e.downcast_ref::<AppError>(), Some(AppError::InvalidHashValue(_))),
+                };
+                if fail_fast && failed {
+                    stop.store(true, Ordering::Relaxed);
+                }
+                tx.send((line, result)).expect("Internal error.");
             });
-            count += 1;
+            queued = true;
         }
 
-        for _ in 0..count {
-            match rx.iter().next().ok_or(AppError::UnknownError)? {
-                Ok((path, is_ok)) => {
-                    if is_ok {
-                        if !opts.quiet {
-                            println!("{}: OK", path.display());
+        // Every job holds a sender, so the channel closes once the last one is done.
+        drop(tx);
+        for (line, result) in rx {
+            match result {
+                Ok((path, verdict, assumed)) => {
+                    let path = match display_dir {
+                        Some(dir) => dir.join(path),
+                        None => path,
+                    };
+                    // With `--verbose`, which algorithm was taken, as a wrong guess only shows as FAILED.
+                    let algorithm = assumed.map(|a| format!(" ({})", a)).unwrap_or_default();
+                    let status = match &verdict {
+                        Verdict::Ok => {
+                            counts.ok += 1;
+                            if json {
+                                counts.files.ok.push(path.clone());
+                            }
+                            // Only failures are shown with `-q`.
+                            (!opts.quiet).then(|| format!("OK{}", algorithm))
+                        }
+                        Verdict::Failed | Verdict::Ambiguous(..) | Verdict::SizeMismatch => {
+                            counts.failed += 1;
+                            if json {
+                                counts.files.failed.push(path.clone());
+                            }
+                            Some(match &verdict {
+                                // Without a header or '-a', nothing tells which algorithm was meant.
+                                Verdict::Ambiguous(guessed, others) => format!("FAILED (checked as {}, use '-a' for {})", guessed, others),
+                                Verdict::Failed => format!("FAILED{}", algorithm),
+                                _ => format!("SIZE MISMATCH{}", algorithm),
+                            })
                         }
-                    } else {
-                        println!("{}: FAILED", path.display());
+                        Verdict::Missing => {
+                            counts.missing += 1;
+                            if json {
+                                counts.files.missing.push(path.clone());
+                            }
+                            Some("MISSING".to_owned())
+                        }
+                        Verdict::Error(reason) => {
+                            counts.unreadable += 1;
+                            counts.errors.push(format!("{}: {}", path.display(), reason));
+                            Some(format!("ERROR ({})", reason))
+                        }
+                    };
+                    if let Some(status) = status.filter(|_| !json) {
+                        println!("{}: {}", path.display(), status);
                     }
-                    all_succeeded &= is_ok;
+                    all_succeeded &= verdict == Verdict::Ok;
                 }
+                // A checksum that can't be decoded is as malformed as an unparsable line.
+                Err(e) if matches!(e.downcast_ref::<AppError>(), Some(AppError::InvalidHashValue(_))) => {
+                    warn(line, &e);
+                    counts.malformed += 1;
+                }
+                // Files that can't be read have their verdict already, what's left are entries that
+                // can't match, e.g. with checksums of another algorithm.
                 Err(e) => {
-                    eprintln!("{}", e);
+                    counts.errors.push(format!("{}: line {}: {}", checksum_file.display(), line, e));
+                    counts.failed += 1;
+                    all_succeeded = false;
                 }
             }
         }
+        if stop.load(Ordering::Relaxed) {
+            eprintln!("Stopped at the first failure, the remaining files in '{}' weren't checked.", checksum_file.display());
+        }
+        if counts.malformed > 0 {
+            eprintln!("WARNING: {} line(s) improperly formatted.", counts.malformed);
+            all_succeeded &= !opts.strict;
+        }
+        if counts.duplicates > 0 {
+            eprintln!("WARNING: {} path(s) listed more than once.", counts.duplicates);
+            all_succeeded &= !opts.strict;
+        }
+        if opts.ignore_missing && counts.missing > 0 {
+            eprintln!("{} missing file(s) skipped.", counts.missing);
+            if !queued {
+                eprintln!("None of the files listed in '{}' was found.", checksum_file.display());
+                all_succeeded = false;
+            }
+        }
+        summary.add(&counts);
     }
     pool.join();
     Ok(all_succeeded)
 }
 
-fn main() -> Result<()> {
-    let args = AppArgs::from_args();
-    match &args.cmd {
-        Commands::G { generation_opts: opts } => {
-            if !generate_checksums(opts)? {
-                std::process::exit(1);
+/// Verifies every checksum file named like the given one found under the directories.
+fn verify_per_directory(opts: &VerificationOpt, summary: &mut Summary) -> Result<bool> {
+    let manifest_name = match &opts.checksum_files[..] {
+        [file] if file != Path::new("-") && file.file_name().is_some() => file.file_name().unwrap_or_default(),
+        _ => return Err(AppError::InvalidOptionError("--per-directory needs a checksum file name".to_owned()).into()),
+    };
+    let mut all_succeeded: bool = true;
+    let mut found = false;
+    for entry in opts.directory.iter().flat_map(|d| WalkDir::new(d).follow_links(true).sort_by_file_name()) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                summary.errors.push(e.to_string());
+                all_succeeded = false;
+                continue;
             }
+        };
+        if entry.file_name() != manifest_name || !entry.path().is_file() {
+            continue;
         }
-        Commands::V { verification_opts: opts } => {
-            if !verify_checksums(opts)? {
-                std::process::exit(1);
+        found = true;
+        let dir = entry.path().parent().unwrap_or_else(|| Path::new(""));
+        let dir = strip_dot_prefix(dir);
+        all_succeeded &= verify_checksum_file(opts, entry.path(), Some(dir), summary)?;
+        if opts.fail_fast && !all_succeeded {
+            break;
+        }
+    }
+    if !found {
+        eprintln!("No '{}' found.", manifest_name.to_string_lossy());
+    }
+    Ok(all_succeeded && found)
+}
+
+/// Lists the files under `dir`, walked as generation would by default, that none of the checksum
+/// files list. Paths are shown as walked, without a leading `./`. Walk errors are added to `errors`.
+fn find_extras(opts: &VerificationOpt, dir: &Path, errors: &mut Vec<String>) -> Result<Vec<PathBuf>> {
+    if opts.per_directory || opts.checksum_files.iter().any(|f| f == Path::new("-")) {
+        return Err(AppError::InvalidOptionError("--check-extra needs checksum files to read again".to_owned()).into());
+    }
+    // Compared by canonical path, as the manifest and the walk may spell the same file differently.
+    // Listed files that are gone can't be extra, so failing to resolve them doesn't matter.
+    let mut listed = HashSet::new();
+    for checksum_file in opts.checksum_files.iter() {
+        let base_dir = base_dir(opts, checksum_file);
+        for (_, entry) in read_entries(Box::new(File::open(checksum_file)?), opts)? {
+            if let Ok(Some(entry)) = entry {
+                let file = resolve_entry_path(base_dir.as_deref(), &entry.path);
+                if let Ok(file) = file.canonicalize() {
+                    listed.insert(file);
+                }
             }
         }
     }
-    Ok(())
+    let manifest_opts = ManifestOptions {
+        exclude: opts.checksum_files.iter().flat_map(|f| [f.clone(), index_path(f)]).collect(),
+        ..ManifestOptions::default()
+    };
+    let mut extras = Vec::new();
+    walk_files(&[dir.to_owned()], &manifest_opts, |entry| match entry.and_then(|p| Ok((p.canonicalize()?, p))) {
+        Ok((canonical, path)) => if !listed.contains(&canonical) {
+            extras.push(strip_dot_prefix(&path).to_owned());
+        }
+        Err(e) => errors.push(e.to_string()),
+    })?;
+    extras.sort();
+    Ok(extras)
+}
+
+fn verify_checksums(opts: &VerificationOpt) -> Result<bool> {
+    // Each per-directory checksum file lists the files next to it.
+    if opts.per_directory && opts.base_dir.is_some() {
+        return Err(AppError::InvalidOptionError("--base-dir can't be used with --per-directory".to_owned()).into());
+    }
+    let mut summary = Summary::default();
+    let mut all_succeeded = true;
+    if opts.per_directory {
+        all_succeeded = verify_per_directory(opts, &mut summary)?;
+    } else {
+        // Each file is read on its own, with its own header or guesses, into a single summary.
+        for checksum_file in opts.checksum_files.iter() {
+            all_succeeded &= verify_checksum_file(opts, checksum_file, None, &mut summary)?;
+            if opts.fail_fast && !all_succeeded {
+                break;
+            }
+        }
+    }
+    // Unlisted files are only reported, unless `--strict-extra` is given.
+    if let Some(dir) = &opts.check_extra {
+        let extras = find_extras(opts, dir, &mut summary.errors)?;
+        summary.extra = extras.len();
+        all_succeeded &= extras.is_empty() || !opts.strict_extra;
+        match opts.format {
+            ReportFormat::Text => {
+                for path in extras.iter() {
+                    println!("{}: EXTRA", path.display());
+                }
+            }
+            ReportFormat::Json => summary.files.extra = extras,
+        }
+    }
+    match opts.format {
+        ReportFormat::Text => {
+            // On stderr, so the per-file lines on stdout stay parseable. Shown with `--quiet` as well.
+            print_errors(&summary.errors);
+            if !opts.no_summary {
+                eprintln!("{}", summary);
+            }
+        }
+        ReportFormat::Json => println!("{}", json_report(&summary)?),
+    }
+    if !summary.errors.is_empty() {
+        return Err(AppError::RunError(summary.errors.len()).into());
+    }
+    // A pattern matching nothing is most likely a mistake.
+    if !opts.only.is_empty() && summary.checked() == 0 {
+        eprintln!("No listed file matches --only.");
+        return Ok(false);
+    }
+    Ok(all_succeeded)
+}
+
+/// The outcome of a verification as a JSON object, with the files by outcome next to the summary.
+fn json_report(summary: &Summary) -> Result<String> {
+    let mut report = serde_json::to_value(&summary.files)?;
+    report["summary"] = serde_json::to_value(summary)?;
+    Ok(serde_json::to_string_pretty(&report)?)
+}
+
+/// Groups files with the same checksum, sorted by path within and across groups. Also returns
+/// whether every file could be hashed.
+fn find_duplicates(opts: &DuplicateOpt) -> Result<(Vec<Vec<PathBuf>>, bool)> {
+    let mut all_succeeded: bool = true;
+    let mut files: HashMap<Vec<u8>, Vec<PathBuf>> = HashMap::new();
+    let manifest_opts = ManifestOptions {
+        num_threads: opts.num_threads.into(),
+        min_size: opts.min_size.map_or(0, u64::from),
+        ..ManifestOptions::default()
+    };
+    walk_checksums(&opts.directory, &[opts.algorithm], &manifest_opts, |event| match event {
+        WalkEvent::Hashed(Ok(mut c)) => {
+            let path = strip_dot_prefix(&c.path).to_owned();
+            files.entry(c.checksums.remove(0)).or_default().push(path);
+        }
+        WalkEvent::Hashed(Err(e)) => {
+            eprintln!("{}", e);
+            all_succeeded = false;
+        }
+        WalkEvent::Error(e) => eprintln!("{}", e),
+        WalkEvent::Queued(_) | WalkEvent::Unchanged(_) => {}
+    })?;
+    let mut groups: Vec<Vec<PathBuf>> = files.into_values().filter(|paths| paths.len() > 1).collect();
+    for paths in groups.iter_mut() {
+        paths.sort();
+    }
+    groups.sort();
+    Ok((groups, all_succeeded))
+}
+
+/// Prints groups of identical files, separated by blank lines.
+fn report_duplicates(opts: &DuplicateOpt) -> Result<bool> {
+    let (groups, all_succeeded) = find_duplicates(opts)?;
+    for (idx, paths) in groups.iter().enumerate() {
+        if idx > 0 {
+            println!();
+        }
+        for path in paths.iter() {
+            println!("{}", path.display());
+        }
+    }
+    Ok(all_succeeded)
+}
+
+/// A file that isn't the same in both trees, by its path relative to them, or in both checksum files.
+#[derive(Debug, PartialEq)]
+enum Difference {
+    OnlyInA(PathBuf),
+    OnlyInB(PathBuf),
+    Differs(PathBuf),
+}
+
+/// Hashes the files under `dir` by their paths relative to it, also returning whether every file
+/// could be read.
+fn hash_tree(dir: &Path, opts: &CompareOpt) -> Result<(BTreeMap<PathBuf, Vec<u8>>, bool)> {
+    let mut all_succeeded: bool = true;
+    let mut files = BTreeMap::new();
+    let manifest_opts = ManifestOptions {
+        num_threads: opts.num_threads.into(),
+        ..ManifestOptions::default()
+    };
+    walk_checksums(&[dir.to_owned()], &[opts.algorithm], &manifest_opts, |event| match event {
+        WalkEvent::Hashed(Ok(mut c)) => {
+            let path = c.path.strip_prefix(dir).unwrap_or(&c.path).to_owned();
+            files.insert(path, c.checksums.remove(0));
+        }
+        WalkEvent::Hashed(Err(e)) | WalkEvent::Error(e) => {
+            eprintln!("{}", e);
+            all_succeeded = false;
+        }
+        WalkEvent::Queued(_) | WalkEvent::Unchanged(_) => {}
+    })?;
+    Ok((files, all_succeeded))
+}
+
+/// Lists the differences between the two trees in path order. Also returns whether every file
+/// could be read, as unreadable ones can't be compared.
+fn compare_trees(opts: &CompareOpt) -> Result<(Vec<Difference>, bool)> {
+    let (a, a_succeeded) = hash_tree(&opts.dir_a, opts)?;
+    let (b, b_succeeded) = hash_tree(&opts.dir_b, opts)?;
+    let differences = a.iter().merge_join_by(b.iter(), |(p1, _), (p2, _)| p1.cmp(p2))
+        .filter_map(|e| match e {
+            EitherOrBoth::Left((path, _)) => Some(Difference::OnlyInA(path.clone())),
+            EitherOrBoth::Right((path, _)) => Some(Difference::OnlyInB(path.clone())),
+            EitherOrBoth::Both((path, c1), (_, c2)) if c1 != c2 => Some(Difference::Differs(path.clone())),
+            EitherOrBoth::Both(..) => None,
+        })
+        .collect();
+    Ok((differences, a_succeeded && b_succeeded))
+}
+
+/// Prints the differences between the two trees, succeeding only if there are none.
+fn report_differences(opts: &CompareOpt) -> Result<bool> {
+    let (differences, all_succeeded) = compare_trees(opts)?;
+    for difference in differences.iter() {
+        match difference {
+            Difference::OnlyInA(path) => println!("{}: only in {}", path.display(), opts.dir_a.display()),
+            Difference::OnlyInB(path) => println!("{}: only in {}", path.display(), opts.dir_b.display()),
+            Difference::Differs(path) => println!("{}: DIFFERENT", path.display()),
+        }
+    }
+    Ok(all_succeeded && differences.is_empty())
+}
+
+/// Checksums of the files listed in a checksum file, by path, in the order they are listed. They are
+/// decoded, so different encodings of the same value compare equal, and whether the algorithm was
+/// named doesn't matter.
+fn read_checksum_map(path: &Path, opts: &DiffOpt) -> Result<BTreeMap<PathBuf, Vec<Vec<u8>>>> {
+    let mut checksums: BTreeMap<PathBuf, Vec<Vec<u8>>> = BTreeMap::new();
+    for (_, entry) in parse_entries(Box::new(File::open(path)?), opts.input_format, opts.algorithm, opts.zero)? {
+        let entry = match entry? {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let checksum = decode_checksum(&entry.checksum, None)?;
+        checksums.entry(strip_dot_prefix(&entry.path).to_owned()).or_default().push(checksum);
+    }
+    Ok(checksums)
+}
+
+/// Lists the entries added, removed or changed from the first checksum file to the second, in path
+/// order.
+fn diff_checksum_files(opts: &DiffOpt) -> Result<Vec<Difference>> {
+    let a = read_checksum_map(&opts.file_a, opts)?;
+    let b = read_checksum_map(&opts.file_b, opts)?;
+    Ok(a.iter().merge_join_by(b.iter(), |(p1, _), (p2, _)| p1.cmp(p2))
+        .filter_map(|e| match e {
+            EitherOrBoth::Left((path, _)) => Some(Difference::OnlyInA(path.clone())),
+            EitherOrBoth::Right((path, _)) => Some(Difference::OnlyInB(path.clone())),
+            EitherOrBoth::Both((path, c1), (_, c2)) if c1 != c2 => Some(Difference::Differs(path.clone())),
+            EitherOrBoth::Both(..) => None,
+        })
+        .collect())
+}
+
+/// Prints the entries that differ between the two checksum files, succeeding only if there are none.
+fn report_checksum_diff(opts: &DiffOpt) -> Result<bool> {
+    let differences = diff_checksum_files(opts)?;
+    for difference in differences.iter() {
+        match difference {
+            Difference::OnlyInA(path) => println!("- {}", path.display()),
+            Difference::OnlyInB(path) => println!("+ {}", path.display()),
+            Difference::Differs(path) => println!("~ {}", path.display()),
+        }
+    }
+    Ok(differences.is_empty())
+}
+
+/// The checksum given to `chksum c`, read from `input` when it is `-`, e.g. pasted with
+/// `pbpaste | chksum c file -`.
+fn read_checksum_arg(checksum: &str, mut input: impl Read) -> Result<String> {
+    if checksum != "-" {
+        return Ok(checksum.to_owned());
+    }
+    let mut checksum = String::new();
+    input.read_to_string(&mut checksum)?;
+    Ok(checksum.trim().to_owned())
+}
+
+/// The names of the other algorithms whose checksums are as long as `algorithm`'s, `None` when
+/// there are none.
+fn same_length(algorithm: Algorithm) -> Option<String> {
+    let others = ALGORITHMS.iter()
+        .filter(|i| i.algorithm != algorithm && i.algorithm.digest_size() == algorithm.digest_size())
+        .map(|i| i.name)
+        .join(", ");
+    (!others.is_empty()).then_some(others)
+}
+
+/// Checks a single file against the checksum on the command line, guessing the algorithm from its
+/// length unless `-a` is given.
+fn check_file(opts: &CheckOpt, input: impl Read) -> Result<bool> {
+    let checksum = read_checksum_arg(&opts.checksum, input)?;
+    let length = decode_checksum(&checksum, None)?.len();
+    let range = ByteRange { offset: opts.offset, length: opts.length };
+    // Lengths several algorithms share are checked with the guessable one, as a hash pasted from
+    // a download page most likely is.
+    let guessed = match opts.algorithm {
+        Some(_) => None,
+        None => Some(likely_algorithm(length).map_or_else(|| guess_algorithm(length), Ok)?),
+    };
+    let (_, matched) = verify_range_checksum(&opts.file, &checksum, opts.algorithm.or(guessed), None, None, range)?;
+    let status = match guessed.filter(|_| !matched).and_then(|a| same_length(a).map(|others| (a, others))) {
+        Some((algorithm, others)) => format!("FAILED (checked as {}, use '-a' for {})", algorithm, others),
+        None if matched => "OK".to_owned(),
+        None => "FAILED".to_owned(),
+    };
+    println!("{}: {}", opts.file.display(), status);
+    Ok(matched)
+}
+
+/// Writes a line per algorithm to `out`, with the size of its checksums and how it can be given to `-a`.
+fn list_algorithms(out: &mut impl Write) -> Result<bool> {
+    writeln!(out, "{:<12} {:>4}  {:<7}  SPELLINGS", "ALGORITHM", "BITS", "GUESSED")?;
+    for info in ALGORITHMS {
+        let spellings = std::iter::once(info.name).chain(info.aliases.iter().copied()).map(str::to_lowercase).join(", ");
+        let guessed = if info.guessable { "yes" } else { "no" };
+        writeln!(out, "{:<12} {:>4}  {:<7}  {}", info.name, info.algorithm.digest_size() * 8, guessed, spellings)?;
+    }
+    Ok(true)
+}
+
+/// Writes the completion script for `shell` to `out`.
+fn print_completions(shell: Shell, out: &mut impl Write) -> Result<bool> {
+    AppArgs::clap().gen_completions_to("chksum", shell, out);
+    Ok(true)
+}
+
+fn main() {
+    let args = AppArgs::from_args();
+    let result = match &args.cmd {
+        Commands::G { generation_opts: opts } => run_generation(opts),
+        Commands::V { verification_opts: opts } => verify_checksums(opts),
+        Commands::D { duplicate_opts: opts } => report_duplicates(opts),
+        Commands::Cmp { compare_opts: opts } => report_differences(opts),
+        Commands::C { check_opts: opts } => check_file(opts, io::stdin()),
+        Commands::Diff { diff_opts: opts } => report_checksum_diff(opts),
+        Commands::Algorithms => list_algorithms(&mut io::stdout()),
+        Commands::Completions { shell } => print_completions(*shell, &mut io::stdout()),
+    };
+    match result {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(EXIT_MISMATCH),
+        Err(e) => {
+            eprintln!("chksum: {}", e);
+            if let Some(AppError::InterruptedError(_)) = e.downcast_ref() {
+                std::process::exit(EXIT_INTERRUPTED);
+            }
+            std::process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::io::{self, Write};
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::{Duration, SystemTime};
+    use anyhow::Result;
+    use structopt::StructOpt;
+    use tempfile::tempdir;
+    use chksum::{ALGORITHMS, Algorithm, AppError, Encoding, calculate_checksum, decode_checksum, encode_checksum};
+    use crate::cmd_line::{AppArgs, CheckOpt, Commands, CompareOpt, DiffOpt, DuplicateOpt, GenerationOpt, PathMode, VerificationOpt};
+    use crate::{
+        Difference, EntryVerifier, Stats, Summary, Verdict, assumed_algorithm, same_length, check_file, is_permission_denied, json_report, compare_trees, create_output, diff_checksum_files, find_duplicates, find_extras, generate_checksums_until,
+        improperly_formatted, list_algorithms, output_path, print_completions, progress_bar, read_entries, read_file_list, report_checksum_diff, strip_dot_prefix, unix_path, verify_checksum_file, verify_checksum_file_with, verify_checksums, verify_entry, write_records,
+    };
+    use crate::manifest::{Entry, Record, Writer};
+
+    /// Generation that's never interrupted.
+    fn generate_checksums(opts: &GenerationOpt) -> Result<bool> {
+        generate_checksums_until(opts, &Arc::new(AtomicBool::new(false)), &mut Stats::default())
+    }
+
+    fn generation_opts(args: &[&str]) -> GenerationOpt {
+        GenerationOpt::from_iter(std::iter::once("g").chain(args.iter().cloned()))
+    }
+
+    fn verification_opts(args: &[&str]) -> VerificationOpt {
+        VerificationOpt::from_iter(std::iter::once("v").chain(args.iter().cloned()))
+    }
+
+    fn path_str(path: &Path) -> &str {
+        path.to_str().unwrap()
+    }
+
+    #[test]
+    fn test_hashdeep_round_trip() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("b,c.txt"), "hello").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--output-format", "hashdeep", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert!(content.starts_with("%%%% HASHDEEP-1.0\n%%%% size,sha256,filename\n"));
+        assert!(content.contains("12,423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b,"));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+
+        fs::write(data.join("a.txt"), "abcdABCD12345").unwrap();
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+    }
+
+    #[test]
+    fn test_hashdeep_hand_crafted() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        fs::write(&sums, format!("%%%% HASHDEEP-1.0\n\
+            %%%% size,md5,sha256,filename\n\
+            ## Invoked from: /tmp\n\
+            ## $ hashdeep -r .\n\
+            ##\n\
+            12,bb057481a1b7abc93ad5d70d52e3a55f,423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b,{}\n", file.display())).unwrap();
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "-a", "sha256"])).unwrap());
+
+        // Correct digest but wrong size must fail on the size check alone.
+        fs::write(&sums, format!("%%%% HASHDEEP-1.0\n\
+            %%%% size,md5,filename\n\
+            13,bb057481a1b7abc93ad5d70d52e3a55f,{}\n", file.display())).unwrap();
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+    }
+
+    #[test]
+    fn test_base64_round_trip() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--encoding", "base64", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert!(content.starts_with("Qj3w2rapfEYjnRlq1vYQ7fVIRlDp5whWNAReiz/BnQs=  "));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "--encoding", "base64"])).unwrap());
+
+        fs::write(data.join("a.txt"), "abcdABCD1235").unwrap();
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+    }
+
+    #[test]
+    fn test_hex_and_base64_agree() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.bin"), vec![0xa5u8; 100 * 1024]).unwrap();
+        let hex = dir.path().join("hex.txt");
+        let base64 = dir.path().join("base64.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&hex), path_str(&data)])).unwrap());
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&base64), "--encoding", "base64", path_str(&data)])).unwrap());
+        let decode = |path: &Path, encoding| {
+            let content = fs::read_to_string(path).unwrap();
+            decode_checksum(content.split_once("  ").unwrap().0, Some(encoding)).unwrap()
+        };
+        assert_eq!(decode(&hex, Encoding::Hex), decode(&base64, Encoding::Base64));
+        // Both are told apart without --encoding.
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&hex)])).unwrap());
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&base64)])).unwrap());
+    }
+
+    #[test]
+    fn test_upper_hex_round_trip() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--encoding", "hex-upper", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert!(content.starts_with("423DF0DAB6A97C46239D196AD6F610EDF5484650E9E7085634045E8B3FC19D0B  "));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "--encoding", "hex-lower"])).unwrap());
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "--encoding", "hex-upper"])).unwrap());
+
+        fs::write(data.join("a.txt"), "abcdABCD1235").unwrap();
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+    }
+
+    #[test]
+    fn test_buffer_size() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        for size in &["1", "5", "1M"] {
+            assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--buffer-size", size, path_str(&data)])).unwrap());
+            assert!(fs::read_to_string(&sums).unwrap().starts_with("423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b  "));
+        }
+        assert!(GenerationOpt::from_iter_safe(&["g", "--buffer-size", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_multiple_algorithms() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("b.txt"), "hello").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "-a", "sha256,md5", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("SHA256 (") && lines[1].starts_with("SHA256 ("));
+        assert!(lines[2].starts_with("MD5 (") && lines[3].starts_with("MD5 ("));
+        assert!(lines[2].ends_with(") = bb057481a1b7abc93ad5d70d52e3a55f"));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "-a", "md5"])).unwrap());
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "-a", "md5,sha256", "--output-format", "hashdeep", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert!(content.contains("%%%% size,md5,sha256,filename\n"));
+        assert!(content.contains("12,bb057481a1b7abc93ad5d70d52e3a55f,423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b,"));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "-a", "sha256"])).unwrap());
+    }
+
+    #[test]
+    fn test_combined_matches_single() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("b.txt"), vec![7u8; 200 * 1024]).unwrap();
+        let single = dir.path().join("single.txt");
+        let combined = dir.path().join("combined.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-a", "sha256", "-f", path_str(&single), path_str(&data)])).unwrap());
+        // The option can be repeated as well as take a list.
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&combined), path_str(&data), "--algorithm", "md5", "--algorithm", "sha256"])).unwrap());
+        let single = fs::read_to_string(&single).unwrap();
+        let combined = fs::read_to_string(&combined).unwrap();
+        let sha256: Vec<String> = combined.lines()
+            .filter_map(|l| l.strip_prefix("SHA256 ("))
+            .map(|l| {
+                let (path, checksum) = l.split_once(") = ").unwrap();
+                format!("{}  {}", checksum, path)
+            })
+            .collect();
+        assert_eq!(sha256, single.lines().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_relative_to_checksum_file() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(data.join("sub")).unwrap();
+        fs::write(data.join("sub").join("a.txt"), "abcdABCD1234").unwrap();
+        let sums = data.join("sums.txt");
+        fs::write(&sums, "423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b  sub/a.txt\n").unwrap();
+
+        // The test runs in the crate directory, which is unrelated to the checksum file.
+        assert!(!Path::new("sub/a.txt").exists());
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+
+        // Absolute paths are used as-is.
+        fs::write(&sums, format!("423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b  {}\n", data.join("sub").join("a.txt").display())).unwrap();
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+    }
+
+    #[test]
+    fn test_base_dir() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        let sums_dir = dir.path().join("sums");
+        fs::create_dir_all(data.join("sub")).unwrap();
+        fs::create_dir(&sums_dir).unwrap();
+        fs::write(data.join("sub").join("a.txt"), "abcdABCD1234").unwrap();
+        let sums = sums_dir.join("sums.txt");
+        fs::write(&sums, "423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b  sub/a.txt\n").unwrap();
+
+        // Neither the current directory nor the one holding the checksum file has the files.
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "--base-dir", path_str(&data)])).unwrap());
+
+        // Absolute paths are still used as-is.
+        fs::write(&sums, format!("423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b  {}\n", data.join("sub").join("a.txt").display())).unwrap();
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "--base-dir", path_str(&sums_dir)])).unwrap());
+
+        assert!(verify_checksums(&verification_opts(&["-f", "sums.txt", "--per-directory", "--base-dir", path_str(&data)])).is_err());
+    }
+
+    #[test]
+    fn test_root_alias() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(data.join("sub")).unwrap();
+        fs::write(data.join("sub").join("a.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+        fs::write(&sums, "bb057481a1b7abc93ad5d70d52e3a55f  sub/a.txt\n").unwrap();
+
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+        for option in ["-d", "--root", "--base-dir"] {
+            let opts = verification_opts(&["-f", path_str(&sums), option, path_str(&data)]);
+            assert_eq!(opts.base_dir.as_deref(), Some(data.as_path()));
+            assert!(verify_checksums(&opts).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_exclude_checksum_file() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("b.txt"), "hello").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        // Run twice so the second walk sees the checksum file from the first run.
+        for _ in 0..2 {
+            assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), path_str(dir.path())])).unwrap());
+            let content = fs::read_to_string(&sums).unwrap();
+            assert_eq!(content.lines().count(), 2);
+            assert!(!content.contains("sums.txt"));
+        }
+
+        // Explicit exclusions don't bring the checksum file back.
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), path_str(dir.path()), "-x", path_str(&dir.path().join("b.txt"))])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.contains("a.txt"));
+    }
+
+    #[test]
+    fn test_zero_terminated() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a\nb.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "-z", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert!(content.ends_with("a\nb.txt\0"));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "--zero"])).unwrap());
+
+        // Reading NUL-terminated records as lines fails without hanging or panicking.
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+    }
+
+    #[test]
+    fn test_no_progress() {
+        assert!(progress_bar(&generation_opts(&["--no-progress"])).is_hidden());
+    }
+
+    #[test]
+    fn test_escaped_file_names() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        for name in &["a\nb", "c\\d", " lead ", "plain"] {
+            fs::write(data.join(name), "x").unwrap();
+        }
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert_eq!(content.lines().count(), 4);
+        assert!(content.contains("\\2d711642b726b04401627ca9fbac32f5c8530fb1903cc4db02258717921a4881  "));
+        assert!(content.contains("a\\nb\n"));
+        assert!(content.contains("c\\\\d\n"));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "-a", "md5,sha256", path_str(&data)])).unwrap());
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+    }
+
+    #[test]
+    fn test_hashdeep_escaped_file_names() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        for name in &["a\nb", "c\\d", "plain"] {
+            fs::write(data.join(name), "x").unwrap();
+        }
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--output-format", "hashdeep", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert_eq!(content.lines().filter(|line| !line.starts_with("%%%%") && !line.starts_with("##")).count(), 3);
+        assert!(content.contains("\n1,2d711642b726b04401627ca9fbac32f5c8530fb1903cc4db02258717921a4881,"));
+        assert!(content.contains("a\\nb\n"));
+        assert!(content.contains("c\\\\d\n"));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+
+        fs::write(data.join("a\nb"), "y").unwrap();
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+    }
+
+    #[test]
+    fn test_coreutils_escaping() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a\\b.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        // As written by `md5sum 'a\b.txt'`.
+        let line = "\\bb057481a1b7abc93ad5d70d52e3a55f  a\\\\b.txt";
+        fs::write(&sums, format!("{}\n", line)).unwrap();
+        let mut summary = Summary::default();
+        assert!(verify_checksum_file(&verification_opts(&["-f", path_str(&sums)]), &sums, None, &mut summary).unwrap());
+        assert_eq!(summary, Summary { ok: 1, ..Summary::default() });
+
+        assert!(generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), path_str(dir.path())])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert_eq!(content, format!("\\bb057481a1b7abc93ad5d70d52e3a55f  {}/a\\\\b.txt\n", dir.path().display()));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+    }
+
+    #[test]
+    fn test_file_name_with_spaces() {
+        let dir = tempdir().unwrap();
+        for name in ["my file.txt", "two  spaces.txt", " leading.txt", "trailing.txt "] {
+            fs::write(dir.path().join(name), "abcdABCD1234").unwrap();
+        }
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), path_str(dir.path())])).unwrap());
+        let mut summary = Summary::default();
+        assert!(verify_checksum_file(&verification_opts(&["-f", path_str(&sums)]), &sums, None, &mut summary).unwrap());
+        assert_eq!(summary, Summary { ok: 4, ..Summary::default() });
+
+        fs::write(&sums, "423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b my file.txt\n").unwrap();
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+    }
+
+    #[test]
+    fn test_binary_mode() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("*b.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--binary", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert!(content.lines().all(|l| l.starts_with("423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b *")));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert!(content.contains("  ") && !content.contains(" *"));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+
+        // As written by `md5sum -b` relative to the checksum file.
+        fs::write(&sums, "bb057481a1b7abc93ad5d70d52e3a55f *data/a.txt\nbb057481a1b7abc93ad5d70d52e3a55f  data/*b.txt\n").unwrap();
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+    }
+
+    #[test]
+    fn test_format_template() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "-a", "md5,sha256", "--format-template", "{algorithm}:{hash},{size},{path}", path_str(dir.path())])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        let expected = format!("MD5:bb057481a1b7abc93ad5d70d52e3a55f,12,{0}\n\
+            SHA256:423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b,12,{0}\n", dir.path().join("a.txt").display());
+        assert_eq!(content, expected);
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("b, \"quoted\"\nname.txt"), "hello").unwrap();
+        let sums = dir.path().join("sums.csv");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "-a", "md5,sha256", "--output-format", "csv", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert!(content.starts_with("path,algorithm,hash,size_bytes,mtime\n"));
+        assert!(content.contains(&format!("{},MD5,bb057481a1b7abc93ad5d70d52e3a55f,12,", data.join("a.txt").display())));
+        assert!(content.contains("b, \"\"quoted\"\"\nname.txt\",SHA256,"));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "--input-format", "csv"])).unwrap());
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "--input-format", "csv", "-a", "md5"])).unwrap());
+
+        fs::write(data.join("b, \"quoted\"\nname.txt"), "hellO").unwrap();
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums), "--input-format", "csv"])).unwrap());
+    }
+
+    #[test]
+    fn test_json_output() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("b \"quoted\"\\\nname.txt"), "hello").unwrap();
+        let sums = dir.path().join("sums.json");
+        let field = |v: &serde_json::Value, name: &str| v[name].as_str().unwrap().to_owned();
+
+        assert!(generate_checksums(&generation_opts(&["-a", "md5,sha256", "-f", path_str(&sums), "--format", "json", path_str(&data)])).unwrap());
+        let entries: Vec<serde_json::Value> = serde_json::from_str(&fs::read_to_string(&sums).unwrap()).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(field(&entries[0], "path"), data.join("a.txt").display().to_string());
+        assert_eq!(field(&entries[0], "algorithm"), "md5");
+        assert_eq!(field(&entries[0], "hash"), "bb057481a1b7abc93ad5d70d52e3a55f");
+        assert_eq!(entries[0]["size"], 12);
+        assert_eq!(field(&entries[1], "algorithm"), "sha256");
+        // Quotes, backslashes and newlines in names survive the round trip.
+        assert_eq!(field(&entries[3], "path"), data.join("b \"quoted\"\\\nname.txt").display().to_string());
+        assert!(entries.iter().all(|e| e.get("path_bytes").is_none()));
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--output-format", "jsonl", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        let entries: Vec<serde_json::Value> = content.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(field(&entries[1], "hash"), "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_json_non_utf8_path() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        let name = std::ffi::OsStr::from_bytes(b"caf\xe9.txt");
+        // Not every file system takes names that aren't UTF-8.
+        if fs::write(data.join(name), "x").is_err() {
+            return;
+        }
+        let sums = dir.path().join("sums.jsonl");
+
+        assert!(generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), "--format", "jsonl", path_str(&data)])).unwrap());
+        let entry: serde_json::Value = serde_json::from_str(fs::read_to_string(&sums).unwrap().trim_end()).unwrap();
+        assert!(entry["path"].as_str().unwrap().ends_with("caf\u{fffd}.txt"));
+        let bytes = base64::decode(entry["path_bytes"].as_str().unwrap()).unwrap();
+        assert_eq!(bytes, data.join(name).as_os_str().as_bytes());
+    }
+
+    #[test]
+    fn test_hmac_round_trip() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "Hi There").unwrap();
+        let key_file = dir.path().join("key");
+        fs::write(&key_file, [0x0bu8; 20]).unwrap();
+        let key_arg = format!("@{}", key_file.display());
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--hmac-key", "0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b0b", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert!(content.starts_with("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7  "));
+        // The header says the checksums are keyed, and is skipped like any comment when verifying.
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--header", "--hmac-key", &key_arg, path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert!(content.lines().any(|l| l == "# keyed: HMAC"));
+        assert!(content.lines().last().unwrap().starts_with("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7  "));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "--hmac-key", &key_arg])).unwrap());
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums), "--hmac-key", "0c0c"])).unwrap());
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+
+        assert!(GenerationOpt::from_iter_safe(&["g", "--hmac-key", "xyz"]).is_err());
+        assert!(GenerationOpt::from_iter_safe(&["g", "--hmac-key", "@/nonexistent/key"]).is_err());
+        // Checksums without a keyed variant fail per file rather than silently ignoring the key.
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "-a", "crc32", "--hmac-key", "0b0b", path_str(&data)])).is_err());
+    }
+
+    #[test]
+    fn test_header() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("abc.txt"), "abc").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        // BLAKE2s can't be told apart from SHA-256 by length, the header names it.
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "-a", "blake2s", "--header", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], format!("# chksum v{}", env!("CARGO_PKG_VERSION")));
+        assert_eq!(lines[1], "# algorithm: BLAKE2s");
+        assert!(lines[2].starts_with("# generated: "));
+        assert!(lines[3].starts_with("508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982  "));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+
+        // Without the header, BLAKE2s checksums are checked as SHA-256 and fail, unless '-a' says otherwise.
+        fs::write(&sums, lines[3..].join("\n")).unwrap();
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "-a", "blake2s"])).unwrap());
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), path_str(&data)])).unwrap());
+        assert!(!fs::read_to_string(&sums).unwrap().starts_with('#'));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+    }
+
+    #[test]
+    fn test_per_directory() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(data.join("sub").join("deeper")).unwrap();
+        fs::create_dir(data.join("empty")).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("sub").join("b.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("sub").join("deeper").join("c.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("z.txt"), "abcdABCD1234").unwrap();
+
+        // Run twice, the manifests from the first run must not be picked up by the second.
+        for _ in 0..2 {
+            assert!(generate_checksums(&generation_opts(&["-f", "sums.txt", "-a", "md5", "--per-directory", path_str(&data)])).unwrap());
+        }
+        assert_eq!(fs::read_to_string(data.join("sums.txt")).unwrap(),
+                   "bb057481a1b7abc93ad5d70d52e3a55f  a.txt\nbb057481a1b7abc93ad5d70d52e3a55f  z.txt\n");
+        assert_eq!(fs::read_to_string(data.join("sub").join("sums.txt")).unwrap(), "bb057481a1b7abc93ad5d70d52e3a55f  b.txt\n");
+        assert_eq!(fs::read_to_string(data.join("sub").join("deeper").join("sums.txt")).unwrap(), "bb057481a1b7abc93ad5d70d52e3a55f  c.txt\n");
+        assert!(!data.join("empty").join("sums.txt").exists());
+        assert!(!Path::new("sums.txt").exists());
+
+        assert!(verify_checksums(&verification_opts(&["-f", "sums.txt", "--per-directory", path_str(&data)])).unwrap());
+        fs::write(data.join("sub").join("deeper").join("c.txt"), "abcdABCD1235").unwrap();
+        assert!(!verify_checksums(&verification_opts(&["-f", "sums.txt", "--per-directory", path_str(&data)])).unwrap());
+        // Nothing to verify is a failure.
+        assert!(!verify_checksums(&verification_opts(&["-f", "sums.txt", "--per-directory", path_str(&data.join("empty"))])).unwrap());
+        assert!(generate_checksums(&generation_opts(&["-f", "-", "--per-directory", path_str(&data)])).is_err());
+    }
+
+    #[test]
+    fn test_no_hidden() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(data.join(".git")).unwrap();
+        fs::create_dir_all(data.join("sub")).unwrap();
+        fs::write(data.join(".git").join("HEAD"), "x").unwrap();
+        fs::write(data.join(".env"), "x").unwrap();
+        fs::write(data.join("a.txt"), "x").unwrap();
+        fs::write(data.join("sub").join("b.txt"), "x").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--no-hidden", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        assert!(!content.contains(".git") && !content.contains(".env"));
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--no-hidden", "--max-depth", "1", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.ends_with("a.txt\n"));
+    }
+
+    #[test]
+    fn test_gitignore() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join(".gitignore"), "*.log\n").unwrap();
+        for name in &["a.txt", "b.log", "c.tmp"] {
+            fs::write(data.join(name), "x").unwrap();
+        }
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), path_str(&data)])).unwrap());
+        assert_eq!(fs::read_to_string(&sums).unwrap().lines().count(), 4);
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--gitignore", "--exclude", "*.tmp", "--exclude", ".*", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.ends_with("a.txt\n"));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+        assert!(GenerationOpt::from_iter_safe(&["g", "--exclude", "a[b"]).is_err());
+    }
+
+    #[test]
+    fn test_ignore_missing() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "x").unwrap();
+        fs::write(data.join("b.txt"), "x").unwrap();
+        let sums = dir.path().join("sums.txt");
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), path_str(&data)])).unwrap());
+
+        fs::remove_file(data.join("b.txt")).unwrap();
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "--ignore-missing"])).unwrap());
+        // Present files are still checked.
+        fs::write(data.join("a.txt"), "y").unwrap();
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums), "--ignore-missing"])).unwrap());
+
+        // With nothing left to verify the run fails.
+        fs::remove_file(data.join("a.txt")).unwrap();
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums), "--ignore-missing"])).unwrap());
+    }
+
+    #[test]
+    fn test_strict() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        fs::write(&sums, format!("garbage\n\
+            bb057481a1b7abc93ad5d70d52e3a55f  {0}\n\
+            bb057481a1b7abc93ad5d70d52e3a55f\n\
+            bb057481a1b7abc93ad5d70d52e3a55f  {0}\n", file.display())).unwrap();
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums), "--strict"])).unwrap());
+
+        fs::write(&sums, format!("bb057481a1b7abc93ad5d70d52e3a55f  {0}\n", file.display())).unwrap();
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "--strict"])).unwrap());
+
+        // A checksum that can't be decoded only makes the line malformed as well.
+        fs::write(&sums, format!("not-a-checksum!  {0}\n\
+            bb057481a1b7abc93ad5d70d52e3a55f  {0}\n", file.display())).unwrap();
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums), "--strict"])).unwrap());
+    }
+
+    #[test]
+    fn test_duplicate_paths() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+        let verify = |args: &[&str]| {
+            let mut summary = Summary::default();
+            let args: Vec<&str> = ["-f", path_str(&sums)].iter().chain(args).cloned().collect();
+            let all_succeeded = verify_checksum_file(&verification_opts(&args), &sums, None, &mut summary).unwrap();
+            (all_succeeded, summary)
+        };
+
+        // The same hash twice only warns, unless `--strict`.
+        fs::write(&sums, format!("bb057481a1b7abc93ad5d70d52e3a55f  {0}\nbb057481a1b7abc93ad5d70d52e3a55f  {0}\n", file.display())).unwrap();
+        assert_eq!(verify(&[]), (true, Summary { ok: 2, duplicates: 1, ..Summary::default() }));
+        assert_eq!(verify(&["--strict"]), (false, Summary { ok: 2, duplicates: 1, ..Summary::default() }));
+
+        // Different hashes, as from a botched merge, are both checked.
+        fs::write(&sums, format!("bb057481a1b7abc93ad5d70d52e3a55f  {0}\n00000000000000000000000000000000  {0}\n", file.display())).unwrap();
+        assert_eq!(verify(&[]), (false, Summary { ok: 1, failed: 1, duplicates: 1, ..Summary::default() }));
+
+        // A path is listed once per algorithm in files with several.
+        fs::write(&sums, format!("MD5 ({0}) = bb057481a1b7abc93ad5d70d52e3a55f\nCRC32 ({0}) = 537eb9fd\n", file.display())).unwrap();
+        assert_eq!(verify(&["--strict"]), (true, Summary { ok: 2, ..Summary::default() }));
+    }
+
+    #[test]
+    fn test_append() {
+        let dir = tempdir().unwrap();
+        let first = dir.path().join("first");
+        let second = dir.path().join("second");
+        fs::create_dir(&first).unwrap();
+        fs::create_dir(&second).unwrap();
+        fs::write(first.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(second.join("b.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "-a", "md5", "--append", "--header", path_str(&first)])).unwrap());
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "-a", "md5", "--append", "--header", path_str(&second)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        // The header is only written once, in front of the first batch.
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines.iter().filter(|l| l.starts_with('#')).count(), 3);
+        assert_eq!(lines[3], format!("bb057481a1b7abc93ad5d70d52e3a55f  {}", first.join("a.txt").display()));
+        assert_eq!(lines[4], format!("bb057481a1b7abc93ad5d70d52e3a55f  {}", second.join("b.txt").display()));
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+
+        assert!(generate_checksums(&generation_opts(&["-f", "-", "--append", path_str(&first)])).is_err());
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--append", "--output-format", "csv", path_str(&first)])).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_follow_symlinks() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(dir.path().join("target.txt"), "x").unwrap();
+        fs::write(data.join("a.txt"), "x").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("target.txt"), data.join("link.txt")).unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), path_str(&data)])).unwrap());
+        assert_eq!(fs::read_to_string(&sums).unwrap().lines().count(), 2);
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--no-follow-symlinks", path_str(&data)])).unwrap());
+        assert_eq!(fs::read_to_string(&sums).unwrap().lines().count(), 1);
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--no-follow-symlinks", "--follow-symlinks", "--cross-file-system", path_str(&data)])).unwrap());
+        assert_eq!(fs::read_to_string(&sums).unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn test_warn_line_numbers() {
+        let dir = tempdir().unwrap();
+        let sums = dir.path().join("sums.txt");
+        fs::write(&sums, "bb057481a1b7abc93ad5d70d52e3a55f  a.txt\n\
+            garbage\n\
+            bb057481a1b7abc93ad5d70d52e3a55f  b.txt\n\
+            bb057481a1b7abc93ad5d70d52e3a55f\n").unwrap();
+        let opts = verification_opts(&["-f", path_str(&sums), "--warn"]);
+        let bad: Vec<String> = read_entries(Box::new(fs::File::open(&sums).unwrap()), &opts).unwrap()
+            .filter(|(_, entry)| entry.is_err())
+            .map(|(line, _)| improperly_formatted(&sums, line))
+            .collect();
+        assert_eq!(bad, vec![
+            format!("chksum: {}: line 2: improperly formatted checksum line", sums.display()),
+            format!("chksum: {}: line 4: improperly formatted checksum line", sums.display()),
+        ]);
+
+        // Rows of CSV files may span several lines.
+        fs::write(&sums, "path,hash\n\"a\nb\",bb057481a1b7abc93ad5d70d52e3a55f\nc\n").unwrap();
+        let opts = verification_opts(&["-f", path_str(&sums), "--input-format", "csv"]);
+        let lines: Vec<(u64, bool)> = read_entries(Box::new(fs::File::open(&sums).unwrap()), &opts).unwrap()
+            .map(|(line, entry)| (line, entry.is_ok()))
+            .collect();
+        assert_eq!(lines, vec![(2, true), (4, false)]);
+    }
+
+    #[test]
+    fn test_update() {
+        let dir = tempdir().unwrap();
+        let files = dir.path().join("files");
+        fs::create_dir(&files).unwrap();
+        fs::write(files.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(files.join("b.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+        let args = ["-a", "md5", "-f", path_str(&sums), "--update", path_str(&files)];
+        assert!(generate_checksums(&generation_opts(&args)).unwrap());
+        assert!(dir.path().join("sums.txt.index").is_file());
+
+        // Same size and modification time, so the stale checksum is carried over unread.
+        let modified = fs::metadata(files.join("a.txt")).unwrap().modified().unwrap();
+        fs::write(files.join("a.txt"), "ABCDabcd1234").unwrap();
+        fs::File::options().write(true).open(files.join("a.txt")).unwrap().set_modified(modified).unwrap();
+        fs::write(files.join("b.txt"), "abcd").unwrap();
+        fs::write(files.join("c.txt"), "abcd").unwrap();
+        assert!(generate_checksums(&generation_opts(&args)).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines, vec![
+            format!("bb057481a1b7abc93ad5d70d52e3a55f  {}", files.join("a.txt").display()),
+            format!("e2fc714c4727ee9395f324cd2e7f331f  {}", files.join("b.txt").display()),
+            format!("e2fc714c4727ee9395f324cd2e7f331f  {}", files.join("c.txt").display()),
+        ]);
+        // Nothing is left behind by the rewrite.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+
+        assert!(generate_checksums(&generation_opts(&["-f", "-", "--update", path_str(&files)])).is_err());
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--update", "--append", path_str(&files)])).is_err());
+    }
+
+    #[test]
+    fn test_resume() {
+        let dir = tempdir().unwrap();
+        let files = dir.path().join("files");
+        fs::create_dir(&files).unwrap();
+        fs::write(files.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(files.join("b.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+        let args = ["-a", "md5", "-f", path_str(&sums), "--header", "--resume", path_str(&files)];
+        assert!(generate_checksums(&generation_opts(&args)).unwrap());
+
+        // A listed file that looks untouched isn't read again, one modified since is.
+        let modified = fs::metadata(files.join("a.txt")).unwrap().modified().unwrap();
+        fs::write(files.join("a.txt"), "ABCDabcd1234").unwrap();
+        fs::File::options().write(true).open(files.join("a.txt")).unwrap().set_modified(modified).unwrap();
+        fs::write(files.join("b.txt"), "abcd").unwrap();
+        fs::File::options().write(true).open(files.join("b.txt")).unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(3600)).unwrap();
+        fs::write(files.join("c.txt"), "abcd").unwrap();
+        assert!(generate_checksums(&generation_opts(&args)).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines.iter().filter(|l| l.starts_with('#')).count(), 3);
+        assert_eq!(lines[3..], [
+            format!("bb057481a1b7abc93ad5d70d52e3a55f  {}", files.join("a.txt").display()),
+            format!("e2fc714c4727ee9395f324cd2e7f331f  {}", files.join("b.txt").display()),
+            format!("e2fc714c4727ee9395f324cd2e7f331f  {}", files.join("c.txt").display()),
+        ]);
+
+        assert!(generate_checksums(&generation_opts(&["-f", "-", "--resume", path_str(&files)])).is_err());
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--resume", "--output-format", "csv", path_str(&files)])).is_err());
+    }
+
+    #[test]
+    fn test_comments_and_blank_lines() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("b.txt"), "abcd").unwrap();
+        let sums = dir.path().join("sums.txt");
+        fs::write(&sums, [
+            "# release files",
+            "",
+            "bb057481a1b7abc93ad5d70d52e3a55f  a.txt",
+            "   ",
+            "  # b is smaller",
+            "e2fc714c4727ee9395f324cd2e7f331f  b.txt",
+            "",
+        ].join("\n")).unwrap();
+
+        let mut summary = Summary::default();
+        let opts = verification_opts(&["-f", path_str(&sums), "--strict"]);
+        assert!(verify_checksum_file(&opts, &sums, None, &mut summary).unwrap());
+        assert_eq!(summary, Summary { ok: 2, ..Summary::default() });
+
+        // Skipped lines still count for the line numbers of the entries after them.
+        let entries: Vec<(u64, PathBuf)> = read_entries(Box::new(fs::File::open(&sums).unwrap()), &opts).unwrap()
+            .filter_map(|(line, entry)| Some((line, entry.unwrap()?.path)))
+            .collect();
+        assert_eq!(entries, vec![(3, PathBuf::from("a.txt")), (6, PathBuf::from("b.txt"))]);
+    }
+
+    #[test]
+    fn test_crlf_and_bom() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("b.txt"), "abcdABCD1235").unwrap();
+        let fixtures = [
+            ("gnu", "sums.txt", "bb057481a1b7abc93ad5d70d52e3a55f  a.txt\nbb057481a1b7abc93ad5d70d52e3a55f  b.txt\n"),
+            ("csv", "sums.csv", "path,hash\na.txt,bb057481a1b7abc93ad5d70d52e3a55f\nb.txt,bb057481a1b7abc93ad5d70d52e3a55f\n"),
+        ];
+        for (format, name, lf) in fixtures {
+            let verify = |content: &str| {
+                let sums = dir.path().join(name);
+                fs::write(&sums, content).unwrap();
+                let mut summary = Summary::default();
+                let succeeded = verify_checksum_file(&verification_opts(&["-f", path_str(&sums), "--input-format", format]), &sums, None, &mut summary).unwrap();
+                (succeeded, summary)
+            };
+            let windows = format!("\u{feff}{}", lf.replace('\n', "\r\n"));
+            assert_eq!(verify(lf), (false, Summary { ok: 1, failed: 1, ..Summary::default() }));
+            assert_eq!(verify(&windows), verify(lf));
+
+            // UTF-16 isn't read at all, but says so.
+            let sums = dir.path().join(name);
+            let utf16: Vec<u8> = format!("\u{feff}{}", lf).encode_utf16().flat_map(u16::to_le_bytes).collect();
+            fs::write(&sums, utf16).unwrap();
+            let opts = verification_opts(&["-f", path_str(&sums), "--input-format", format]);
+            let e = verify_checksum_file(&opts, &sums, None, &mut Summary::default()).unwrap_err();
+            assert_eq!(e.to_string(), "The checksum file is encoded in UTF-16, convert it to UTF-8 first.");
+        }
+    }
+
+    #[test]
+    fn test_only() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("b.iso"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("sub").join("c.iso"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+        fs::write(&sums, [
+            "bb057481a1b7abc93ad5d70d52e3a55f  a.txt",
+            "bb057481a1b7abc93ad5d70d52e3a55f  b.iso",
+            "bb057481a1b7abc93ad5d70d52e3a55f  sub/c.iso",
+            "e2fc714c4727ee9395f324cd2e7f331f  broken.txt",
+            "",
+        ].join("\n")).unwrap();
+        let verify = |args: &[&str]| {
+            let mut summary = Summary::default();
+            let opts = verification_opts(&[&["-f", path_str(&sums)], args].concat());
+            (verify_checksum_file(&opts, &sums, None, &mut summary).unwrap(), summary)
+        };
+
+        // Entries not selected don't count, even the failing ones.
+        let (ok, summary) = verify(&["--only", "*.iso"]);
+        assert!(ok);
+        assert_eq!(summary, Summary { ok: 2, filtered: 2, ..Summary::default() });
+        assert!(summary.to_string().ends_with(", 2 filtered out"));
+        let (ok, summary) = verify(&["--only", "a.txt", "--only", "sub/c.iso"]);
+        assert!(ok);
+        assert_eq!(summary, Summary { ok: 2, filtered: 2, ..Summary::default() });
+        let (ok, summary) = verify(&["--only", "*.txt"]);
+        assert!(!ok);
+        assert_eq!(summary, Summary { ok: 1, missing: 1, filtered: 2, ..Summary::default() });
+
+        assert!(!verify_checksums(&verification_opts(&["-f", path_str(&sums), "--only", "nothing"])).unwrap());
+    }
+
+    #[test]
+    fn test_mixed_algorithms() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("b.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+        fs::write(&sums, [
+            "bb057481a1b7abc93ad5d70d52e3a55f  a.txt",
+            "423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b  b.txt",
+            "",
+        ].join("\n")).unwrap();
+        let verify = |args: &[&str]| {
+            let mut summary = Summary::default();
+            let opts = verification_opts(&[&["-f", path_str(&sums)], args].concat());
+            let succeeded = verify_checksum_file(&opts, &sums, None, &mut summary).unwrap();
+            let errors = std::mem::take(&mut summary.errors).len();
+            (succeeded, summary, errors)
+        };
+
+        // Each line gets its algorithm from its length unless one is given, the others can't be
+        // checked at all.
+        assert_eq!(verify(&[]), (true, Summary { ok: 2, ..Summary::default() }, 0));
+        assert_eq!(verify(&["-a", "sha256"]), (false, Summary { ok: 1, failed: 1, ..Summary::default() }, 1));
+        assert_eq!(verify(&["-a", "md5"]), (false, Summary { ok: 1, failed: 1, ..Summary::default() }, 1));
+        assert_eq!(verify(&["-a", "sha256", "--mixed"]), (true, Summary { ok: 2, ..Summary::default() }, 0));
+    }
+
+    #[test]
+    fn test_fail_fast() {
+        let dir = tempdir().unwrap();
+        let mut lines = vec!["e2fc714c4727ee9395f324cd2e7f331f  a.txt".to_owned()];
+        fs::write(dir.path().join("a.txt"), "abcdABCD1234").unwrap();
+        for i in 0..200 {
+            fs::write(dir.path().join(format!("{}.txt", i)), "abcdABCD1234").unwrap();
+            lines.push(format!("bb057481a1b7abc93ad5d70d52e3a55f  {}.txt", i));
+        }
+        let sums = dir.path().join("sums.txt");
+        fs::write(&sums, lines.join("\n")).unwrap();
+        let verify = |args: &[&str]| {
+            let mut summary = Summary::default();
+            let opts = verification_opts(&[&["-f", path_str(&sums), "-n", "1", "-q"], args].concat());
+            (verify_checksum_file(&opts, &sums, None, &mut summary).unwrap(), summary)
+        };
+
+        assert_eq!(verify(&[]), (false, Summary { ok: 200, failed: 1, ..Summary::default() }));
+        // With a single thread, nothing after the mismatch on the first line is read.
+        assert_eq!(verify(&["--fail-fast"]), (false, Summary { failed: 1, ..Summary::default() }));
+
+        // With several threads, only the files already being read when the mismatch is seen get
+        // finished, and each thread takes at most one more job before seeing it.
+        let reads = Arc::new(AtomicUsize::new(0));
+        let counted: Arc<EntryVerifier> = {
+            let reads = reads.clone();
+            Arc::new(move |file: &Path, entry: &Entry, algorithm, encoding, key: Option<&[u8]>, mixed| {
+                reads.fetch_add(1, Ordering::SeqCst);
+                // The mismatch comes back right away, the others take their time.
+                if entry.path != Path::new("a.txt") {
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+                verify_entry(file, entry, algorithm, encoding, key, mixed)
+            })
+        };
+        let threads = 4;
+        let opts = verification_opts(&["-f", path_str(&sums), "-n", &threads.to_string(), "-q", "--fail-fast"]);
+        let mut summary = Summary::default();
+        assert!(!verify_checksum_file_with(&opts, &sums, None, &mut summary, counted.clone()).unwrap());
+        assert_eq!(summary.failed, 1);
+        assert!(reads.load(Ordering::SeqCst) <= 2 * threads, "{} files read", reads.load(Ordering::SeqCst));
+        assert_eq!(summary.ok, reads.load(Ordering::SeqCst) - 1);
+
+        // Without it, everything is read.
+        reads.store(0, Ordering::SeqCst);
+        let opts = verification_opts(&["-f", path_str(&sums), "-n", &threads.to_string(), "-q"]);
+        assert!(!verify_checksum_file_with(&opts, &sums, None, &mut Summary::default(), counted).unwrap());
+        assert_eq!(reads.load(Ordering::SeqCst), 201);
+    }
+
+    #[test]
+    fn test_with_size() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("b.txt"), "abcd").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), "--with-size", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines, vec![
+            "# columns: checksum size path".to_owned(),
+            format!("bb057481a1b7abc93ad5d70d52e3a55f  12  {}", data.join("a.txt").display()),
+            format!("e2fc714c4727ee9395f324cd2e7f331f  4  {}", data.join("b.txt").display()),
+        ]);
+        let verify = || {
+            let mut summary = Summary::default();
+            let opts = verification_opts(&["-f", path_str(&sums)]);
+            (verify_checksum_file(&opts, &sums, None, &mut summary).unwrap(), summary)
+        };
+        assert_eq!(verify(), (true, Summary { ok: 2, ..Summary::default() }));
+
+        // A file of another size fails without being hashed, which a directory couldn't be.
+        fs::write(data.join("a.txt"), "abcdABCD123").unwrap();
+        fs::remove_file(data.join("b.txt")).unwrap();
+        fs::create_dir(data.join("b.txt")).unwrap();
+        assert_eq!(verify(), (false, Summary { failed: 2, ..Summary::default() }));
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--with-size", "--output-format", "csv", path_str(&data)])).is_err());
+    }
+
+    #[test]
+    fn test_summary() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("b.txt"), "abcdABCD1234").unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let sums = dir.path().join("sums.txt");
+        fs::write(&sums, [
+            "bb057481a1b7abc93ad5d70d52e3a55f  a.txt",
+            "e2fc714c4727ee9395f324cd2e7f331f  b.txt",
+            "bb057481a1b7abc93ad5d70d52e3a55f  missing.txt",
+            "bb057481a1b7abc93ad5d70d52e3a55f  sub",
+            "garbage",
+            "",
+        ].join("\n")).unwrap();
+
+        let mut summary = Summary::default();
+        assert!(!verify_checksum_file(&verification_opts(&["-f", path_str(&sums), "-q"]), &sums, None, &mut summary).unwrap());
+        // The reason comes from the OS.
+        let errors = summary.errors.clone();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("sub: "));
+        assert_eq!(summary, Summary { ok: 1, failed: 1, missing: 1, unreadable: 1, malformed: 1, errors, ..Summary::default() });
+        assert_eq!(summary.to_string(), "1 OK, 1 FAILED, 1 missing, 1 unreadable, 1 malformed line");
+
+        // Skipped missing files are counted too, and the counts add up across checksum files.
+        verify_checksum_file(&verification_opts(&["-f", path_str(&sums), "--ignore-missing"]), &sums, None, &mut summary).unwrap();
+        let errors = [summary.errors[0].clone(), summary.errors[0].clone()].to_vec();
+        assert_eq!(summary, Summary { ok: 2, failed: 2, missing: 2, unreadable: 2, malformed: 2, errors, ..Summary::default() });
+        assert!(summary.to_string().ends_with("2 malformed lines"));
+    }
+
+    #[test]
+    fn test_write_error_keeps_original() {
+        struct FailingWriter(usize);
+
+        impl Write for FailingWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                if self.0 < buf.len() {
+                    return Err(io::Error::other("disk full"));
+                }
+                self.0 -= buf.len();
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let sums = dir.path().join("sums.txt");
+        fs::write(&sums, "bb057481a1b7abc93ad5d70d52e3a55f  a.txt\n").unwrap();
+        let opts = generation_opts(&["-a", "md5", "-f", path_str(&sums)]);
+        let records: Vec<Record> = (0..10).map(|i| Record {
+            path: format!("{}.txt", i).into(),
+            checksums: vec!["bb057481a1b7abc93ad5d70d52e3a55f".to_owned()],
+            size: 12,
+            modified: SystemTime::now(),
+        }).collect();
+
+        // The real output is swapped for one failing after a few records.
+        let mut output = create_output(&sums, &opts).unwrap();
+        output.writer = Writer::new(Box::new(FailingWriter(100)), false, false);
+        assert!(write_records(&mut output.writer, &records, &opts, output.appending).is_err());
+        drop(output);
+        assert_eq!(fs::read_to_string(&sums).unwrap(), "bb057481a1b7abc93ad5d70d52e3a55f  a.txt\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // Once complete, the new file replaces the original.
+        let mut output = create_output(&sums, &opts).unwrap();
+        write_records(&mut output.writer, &records, &opts, output.appending).unwrap();
+        output.commit().unwrap();
+        assert_eq!(fs::read_to_string(&sums).unwrap().lines().count(), 10);
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_walk_errors() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            fs::write(data.join(name), "abcdABCD1234").unwrap();
+        }
+        // Followed links to nothing are errors, but the other files are still listed.
+        std::os::unix::fs::symlink(data.join("gone"), data.join("dangling")).unwrap();
+        std::os::unix::fs::symlink(data.join("gone"), data.join("dangling2")).unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        let e = generate_checksums(&generation_opts(&["-a", "md5", "-n", "1", "-f", path_str(&sums), path_str(&data)])).unwrap_err();
+        assert!(matches!(e.downcast_ref::<AppError>(), Some(AppError::RunError(2))));
+        let content = fs::read_to_string(&sums).unwrap();
+        assert_eq!(content.lines().count(), 3);
+        assert!(content.lines().all(|l| l.starts_with("bb057481a1b7abc93ad5d70d52e3a55f  ")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_link_flags() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        let outside = dir.path().join("outside");
+        fs::create_dir_all(&data).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(outside.join("b.txt"), "abcdABCD1234").unwrap();
+        std::os::unix::fs::symlink(&outside, data.join("linked")).unwrap();
+        std::os::unix::fs::symlink(&data, data.join("loop")).unwrap();
+        let sums = dir.path().join("sums.txt");
+        let generate = |args: &[&str]| {
+            let result = generate_checksums(&generation_opts(&[&["-f", path_str(&sums)], args, &[path_str(&data)]].concat()));
+            (result, fs::read_to_string(&sums).unwrap().lines().count())
+        };
+
+        // The loop is an error, but everything else is still listed.
+        let (result, count) = generate(&[]);
+        assert!(matches!(result.unwrap_err().downcast_ref::<AppError>(), Some(AppError::RunError(1))));
+        assert_eq!(count, 2);
+        let (result, count) = generate(&["--no-follow-links"]);
+        assert!(result.unwrap());
+        assert_eq!(count, 1);
+        let (result, count) = generate(&["--cross-filesystems", "--no-follow-links"]);
+        assert!(result.unwrap());
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("x")).unwrap();
+        fs::create_dir(dir.path().join("y")).unwrap();
+        fs::write(dir.path().join("x").join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("y").join("b.txt"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("c.txt"), "abcd").unwrap();
+        fs::write(dir.path().join("x").join("d.txt"), "abcd").unwrap();
+        fs::write(dir.path().join("y").join("e.txt"), "unique").unwrap();
+
+        let opts = DuplicateOpt::from_iter(&["d", path_str(dir.path())]);
+        let (groups, all_succeeded) = find_duplicates(&opts).unwrap();
+        assert!(all_succeeded);
+        assert_eq!(groups, vec![
+            vec![dir.path().join("c.txt"), dir.path().join("x").join("d.txt")],
+            vec![dir.path().join("x").join("a.txt"), dir.path().join("y").join("b.txt")],
+        ]);
+
+        // Small files are left out, `dup` is the same command.
+        let opts = match AppArgs::from_iter(&["chksum", "dup", "--min-size", "5", path_str(dir.path())]).cmd {
+            Commands::D { duplicate_opts } => duplicate_opts,
+            _ => unreachable!(),
+        };
+        let (groups, _) = find_duplicates(&opts).unwrap();
+        assert_eq!(groups, vec![vec![dir.path().join("x").join("a.txt"), dir.path().join("y").join("b.txt")]]);
+        assert!(AppArgs::from_iter_safe(&["chksum", "dup", "--min-size", "0"]).is_ok());
+    }
+
+    #[test]
+    fn test_missing_and_unreadable() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("deleted.txt"), "abcdABCD1234").unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        let entry = |path: &str| Entry {
+            path: path.into(),
+            checksum: "bb057481a1b7abc93ad5d70d52e3a55f".to_owned(),
+            size: None,
+            algorithm: None,
+        };
+        let verify = |path: &str| verify_entry(&dir.path().join(path), &entry(path), None, None, None, false).unwrap();
+
+        assert_eq!(verify("a.txt"), Verdict::Ok);
+        assert_eq!(verify("deleted.txt"), Verdict::Ok);
+        fs::remove_file(dir.path().join("deleted.txt")).unwrap();
+        assert_eq!(verify("deleted.txt"), Verdict::Missing);
+        assert!(matches!(verify("sub"), Verdict::Error(_)));
+
+        let sums = dir.path().join("sums.txt");
+        fs::write(&sums, "bb057481a1b7abc93ad5d70d52e3a55f  a.txt\nbb057481a1b7abc93ad5d70d52e3a55f  deleted.txt\n").unwrap();
+        let mut summary = Summary::default();
+        assert!(!verify_checksum_file(&verification_opts(&["-f", path_str(&sums)]), &sums, None, &mut summary).unwrap());
+        assert_eq!(summary, Summary { ok: 1, missing: 1, ..Summary::default() });
+    }
+
+    #[test]
+    fn test_compare_trees() {
+        let dir = tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        for tree in [&a, &b] {
+            fs::create_dir_all(tree.join("sub")).unwrap();
+            fs::write(tree.join("same.txt"), "abcdABCD1234").unwrap();
+            fs::write(tree.join("sub").join("changed.txt"), "abcdABCD1234").unwrap();
+        }
+        let opts = CompareOpt::from_iter(&["cmp", path_str(&a), path_str(&b)]);
+        assert_eq!(compare_trees(&opts).unwrap(), (vec![], true));
+
+        fs::write(b.join("sub").join("changed.txt"), "abcdABCD1235").unwrap();
+        fs::write(a.join("only_a.txt"), "a").unwrap();
+        fs::write(b.join("sub").join("only_b.txt"), "b").unwrap();
+        assert_eq!(compare_trees(&opts).unwrap(), (vec![
+            Difference::OnlyInA(PathBuf::from("only_a.txt")),
+            Difference::Differs(Path::new("sub").join("changed.txt")),
+            Difference::OnlyInB(Path::new("sub").join("only_b.txt")),
+        ], true));
+    }
+
+    #[test]
+    fn test_check_extra() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(data.join("sub")).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("sub").join("b.txt"), "abcdABCD1234").unwrap();
+        let sums = data.join("sums.txt");
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--update", path_str(&data)])).unwrap());
+        let data_str = path_str(&data);
+        let opts = |strict: bool| {
+            let mut args = vec!["-f", path_str(&sums), "--check-extra", data_str];
+            if strict {
+                args.push("--strict-extra");
+            }
+            verification_opts(&args)
+        };
+        // Neither the checksum file nor its index counts as extra.
+        assert!(data.join("sums.txt.index").exists());
+        assert_eq!(find_extras(&opts(false), &data, &mut Vec::new()).unwrap(), Vec::<PathBuf>::new());
+        assert!(verify_checksums(&opts(true)).unwrap());
+
+        fs::write(data.join("added.txt"), "new").unwrap();
+        fs::rename(data.join("sub").join("b.txt"), data.join("sub").join("renamed.txt")).unwrap();
+        assert_eq!(find_extras(&opts(false), &data, &mut Vec::new()).unwrap(), vec![data.join("added.txt"), data.join("sub").join("renamed.txt")]);
+        // With the listed file back, only the extra ones are left, which fail with `--strict-extra`.
+        fs::write(data.join("sub").join("b.txt"), "abcdABCD1234").unwrap();
+        assert!(verify_checksums(&opts(false)).unwrap());
+        assert!(!verify_checksums(&opts(true)).unwrap());
+    }
+
+    #[test]
+    fn test_check_file() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "abcdABCD1234").unwrap();
+        let check = |args: &[&str], input: &str| {
+            let opts = CheckOpt::from_iter(&[&["c", path_str(&file)], args].concat());
+            check_file(&opts, input.as_bytes())
+        };
+
+        assert!(check(&["bb057481a1b7abc93ad5d70d52e3a55f"], "").unwrap());
+        assert!(check(&["uwV0gaG3q8k61dcNUuOlXw=="], "").unwrap());
+        assert!(check(&["0xbb057481a1b7abc93ad5d70d52e3a55f"], "").unwrap());
+        assert!(check(&["-"], "0xbb057481a1b7abc93ad5d70d52e3a55f\n").unwrap());
+        assert!(check(&["-"], "BB057481A1B7ABC93AD5D70D52E3A55F\n").unwrap());
+        // Checked as MD5 without '-a', even though XXH128 checksums are as long.
+        assert!(!check(&["bb057481a1b7abc93ad5d70d52e3a55e"], "").unwrap());
+        assert!(!check(&["-a", "xxh128", "bb057481a1b7abc93ad5d70d52e3a55f"], "").unwrap());
+        assert_eq!(same_length(Algorithm::MD5).as_deref(), Some("XXH128"));
+        assert_eq!(same_length(Algorithm::SHA1), None);
+
+        // Lengths no algorithm has, or not the one given, are errors rather than mismatches.
+        let e = check(&["bb057481a1"], "").unwrap_err();
+        assert!(matches!(e.downcast_ref::<AppError>(), Some(AppError::UnknownAlgorithmError(5))));
+        let e = check(&["-a", "sha256", "bb057481a1b7abc93ad5d70d52e3a55f"], "").unwrap_err();
+        assert!(matches!(e.downcast_ref::<AppError>(), Some(AppError::DigestLengthError(..))));
+
+        // Only part of the file, e.g. what a partial download should have.
+        assert!(check(&["--offset", "4", "--length", "4", "cb08ca4a7bb5f9683c19133a84872ca7"], "").unwrap());
+        assert!(check(&["--offset", "8", "81dc9bdb52d04dc20036dbd8313ed055"], "").unwrap());
+        assert!(!check(&["--offset", "4", "cb08ca4a7bb5f9683c19133a84872ca7"], "").unwrap());
+    }
+
+    #[test]
+    fn test_byte_range() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.bin"), "xxxxabcdABCD1234").unwrap();
+        fs::write(data.join("b.bin"), "yyyyabcdABCD1234zzzz").unwrap();
+        fs::write(data.join("c.bin"), "xx").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), "--offset", "4", "--length", "12", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        let checksums: Vec<&str> = content.lines().map(|l| l.split_once("  ").unwrap().0).collect();
+        // Whatever is around the range doesn't matter, and short files hash as empty.
+        assert_eq!(checksums, ["bb057481a1b7abc93ad5d70d52e3a55f", "bb057481a1b7abc93ad5d70d52e3a55f", "d41d8cd98f00b204e9800998ecf8427e"]);
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--decompress", "--length", "1", path_str(&data)])).is_err());
+    }
+
+    #[test]
+    fn test_no_recursive() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(data.join("sub").join("deeper")).unwrap();
+        fs::write(data.join("top.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("sub").join("nested.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("sub").join("deeper").join("deepest.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+        let listed = |args: &[&str]| {
+            assert!(generate_checksums(&generation_opts(&[&["-f", path_str(&sums)], args, &[path_str(&data)]].concat())).unwrap());
+            fs::read_to_string(&sums).unwrap().lines().count()
+        };
+
+        assert_eq!(listed(&[]), 3);
+        assert_eq!(listed(&["--no-recursive"]), 1);
+        assert!(fs::read_to_string(&sums).unwrap().ends_with(&format!("{}\n", data.join("top.txt").display())));
+        assert_eq!(listed(&["--max-depth", "2", "--no-recursive"]), 1);
+        // The last of the pair wins.
+        assert_eq!(listed(&["--no-recursive", "--recursive"]), 3);
+    }
+
+    #[test]
+    fn test_depth_limits() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        for name in ["top.txt", "a/one.txt", "a/b/two.txt", "a/b/c/three.txt", "d/one.txt"] {
+            fs::create_dir_all(data.join(name).parent().unwrap()).unwrap();
+            fs::write(data.join(name), "x").unwrap();
+        }
+        let sums = dir.path().join("sums.txt");
+        let listed = |args: &[&str]| {
+            assert!(generate_checksums(&generation_opts(&[&["-f", path_str(&sums)], args, &[path_str(&data)]].concat())).unwrap());
+            let content = fs::read_to_string(&sums).unwrap();
+            // Paths are written the same way whatever the depth.
+            let mut names: Vec<String> = content.lines()
+                .map(|l| unix_path(Path::new(l.split_once("  ").unwrap().1).strip_prefix(&data).unwrap()).display().to_string())
+                .collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(listed(&["--max-depth", "1"]), ["top.txt"]);
+        assert_eq!(listed(&["--max-depth", "2"]), ["a/one.txt", "d/one.txt", "top.txt"]);
+        assert_eq!(listed(&["--min-depth", "2", "--max-depth", "2"]), ["a/one.txt", "d/one.txt"]);
+        assert_eq!(listed(&["--min-depth", "3"]), ["a/b/c/three.txt", "a/b/two.txt"]);
+        assert_eq!(listed(&["--gitignore", "--min-depth", "3"]), ["a/b/c/three.txt", "a/b/two.txt"]);
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--min-depth", "3", "--max-depth", "2"])).is_err());
+    }
+
+    #[test]
+    fn test_windows_separators() {
+        let dir = tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("sub").join("deeper")).unwrap();
+        fs::write(dir.path().join("sub").join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("sub").join("deeper").join("b.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+        fs::write(&sums, [
+            "bb057481a1b7abc93ad5d70d52e3a55f  sub\\a.txt",
+            "bb057481a1b7abc93ad5d70d52e3a55f  sub\\deeper\\b.txt",
+            "",
+        ].join("\r\n")).unwrap();
+        let mut summary = Summary::default();
+        assert!(verify_checksum_file(&verification_opts(&["-f", path_str(&sums)]), &sums, None, &mut summary).unwrap());
+        assert_eq!(summary, Summary { ok: 2, ..Summary::default() });
+
+        // A file actually named with a backslash comes first.
+        fs::write(dir.path().join("sub\\a.txt"), "something else").unwrap();
+        let mut summary = Summary::default();
+        assert!(!verify_checksum_file(&verification_opts(&["-f", path_str(&sums)]), &sums, None, &mut summary).unwrap());
+        assert_eq!(summary, Summary { ok: 1, failed: 1, ..Summary::default() });
+
+        // Both separators in one path, as when a Windows path is joined to a relative one.
+        fs::write(&sums, "bb057481a1b7abc93ad5d70d52e3a55f  sub\\deeper/b.txt\nbb057481a1b7abc93ad5d70d52e3a55f  ./sub/deeper\\b.txt\n").unwrap();
+        let mut summary = Summary::default();
+        assert!(verify_checksum_file(&verification_opts(&["-f", path_str(&sums)]), &sums, None, &mut summary).unwrap());
+        assert_eq!(summary, Summary { ok: 2, ..Summary::default() });
+    }
+
+    #[test]
+    fn test_path_style() {
+        assert_eq!(strip_dot_prefix(Path::new("./a/b.txt")), Path::new("a/b.txt"));
+        assert_eq!(strip_dot_prefix(Path::new("../a.txt")), Path::new("../a.txt"));
+        assert_eq!(unix_path(&Path::new("a").join("b.txt")), PathBuf::from("a/b.txt"));
+
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub").join("a.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+        assert!(generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), "--path-style", "unix", path_str(dir.path())])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert!(content.ends_with("/sub/a.txt\n"));
+        assert!(GenerationOpt::from_iter_safe(&["g", "--path-style", "dos"]).is_err());
+        for flag in ["--slash", "--posix-paths"] {
+            fs::remove_file(&sums).unwrap();
+            assert!(generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), flag, path_str(dir.path())])).unwrap());
+            assert_eq!(fs::read_to_string(&sums).unwrap(), content);
+        }
+    }
+
+    #[test]
+    fn test_files_from() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(data.join("sub")).unwrap();
+        for name in ["a.txt", "b.txt", "sub/c.txt"] {
+            fs::write(data.join(name), "abcdABCD1234").unwrap();
+        }
+        let list = dir.path().join("list.txt");
+        fs::write(&list, format!("{}\n\n{}\n", data.join("a.txt").display(), data.join("sub").join("c.txt").display())).unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        // The directory isn't walked, only the listed files are hashed.
+        assert!(generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), "--files-from", path_str(&list), path_str(&data)])).unwrap());
+        assert_eq!(fs::read_to_string(&sums).unwrap(), format!(
+            "bb057481a1b7abc93ad5d70d52e3a55f  {}\nbb057481a1b7abc93ad5d70d52e3a55f  {}\n",
+            data.join("a.txt").display(), data.join("sub").join("c.txt").display(),
+        ));
+
+        fs::write(&list, format!("{}\0{}\0", data.join("a.txt").display(), data.join("gone.txt").display())).unwrap();
+        let e = generate_checksums(&generation_opts(&["-z", "-f", path_str(&sums), "--files-from", path_str(&list)])).unwrap_err();
+        assert!(matches!(e.downcast_ref::<AppError>(), Some(AppError::RunError(1))));
+        assert_eq!(fs::read_to_string(&sums).unwrap().split('\0').count(), 2);
+
+        // `-0` only changes how the list is read, the output still has a line per file.
+        let e = generate_checksums(&generation_opts(&["-0", "-f", path_str(&sums), "--files-from", path_str(&list)])).unwrap_err();
+        assert!(matches!(e.downcast_ref::<AppError>(), Some(AppError::RunError(1))));
+        assert_eq!(fs::read_to_string(&sums).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_read_file_list() {
+        // As piped from `find` on stdin.
+        let read = |input: &'static [u8], zero| read_file_list(Box::new(input), zero).unwrap();
+        assert_eq!(read(b"./a.txt\n\n./b c.txt\r\n", false), [PathBuf::from("./a.txt"), PathBuf::from("./b c.txt")]);
+        assert_eq!(read(b"./a.txt\0\0./new\nline.txt\0", true), [PathBuf::from("./a.txt"), PathBuf::from("./new\nline.txt")]);
+        assert!(read(b"", false).is_empty());
+    }
+
+    #[test]
+    fn test_diff_checksum_files() {
+        let dir = tempdir().unwrap();
+        let (a, b) = (dir.path().join("old.md5"), dir.path().join("new.md5"));
+        fs::write(&a, [
+            "bb057481a1b7abc93ad5d70d52e3a55f  same.txt",
+            "bb057481a1b7abc93ad5d70d52e3a55f  changed.txt",
+            "bb057481a1b7abc93ad5d70d52e3a55f  removed.txt",
+            "bb057481a1b7abc93ad5d70d52e3a55f  ./reencoded.txt",
+            "",
+        ].join("\n")).unwrap();
+        fs::write(&b, [
+            "# algorithm: MD5",
+            "bb057481a1b7abc93ad5d70d52e3a55e  changed.txt",
+            "bb057481a1b7abc93ad5d70d52e3a55f  added.txt",
+            "BB057481A1B7ABC93AD5D70D52E3A55F  reencoded.txt",
+            "bb057481a1b7abc93ad5d70d52e3a55f  same.txt",
+            "",
+        ].join("\n")).unwrap();
+        let opts = |a: &Path, b: &Path| DiffOpt::from_iter(&["diff", path_str(a), path_str(b)]);
+
+        assert_eq!(diff_checksum_files(&opts(&a, &b)).unwrap(), vec![
+            Difference::OnlyInB(PathBuf::from("added.txt")),
+            Difference::Differs(PathBuf::from("changed.txt")),
+            Difference::OnlyInA(PathBuf::from("removed.txt")),
+        ]);
+        assert!(!report_checksum_diff(&opts(&a, &b)).unwrap());
+        assert!(report_checksum_diff(&opts(&a, &a)).unwrap());
+
+        fs::write(&b, "garbage\n").unwrap();
+        assert!(diff_checksum_files(&opts(&a, &b)).is_err());
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        for (name, size) in [("tiny.conf", 10), ("small.txt", 1024), ("medium.bin", 5000), ("large.iso", 3 << 20)] {
+            fs::write(data.join(name), vec![b'x'; size]).unwrap();
+        }
+        let sums = dir.path().join("sums.txt");
+        let listed = |args: &[&str]| {
+            assert!(generate_checksums(&generation_opts(&[&["-f", path_str(&sums)], args, &[path_str(&data)]].concat())).unwrap());
+            let content = fs::read_to_string(&sums).unwrap();
+            let mut names: Vec<String> = content.lines().map(|l| l.rsplit('/').next().unwrap().to_owned()).collect();
+            names.sort();
+            names
+        };
+
+        assert_eq!(listed(&[]).len(), 4);
+        // Both limits are inclusive.
+        assert_eq!(listed(&["--min-size", "1K"]), ["large.iso", "medium.bin", "small.txt"]);
+        assert_eq!(listed(&["--max-size", "1K"]), ["small.txt", "tiny.conf"]);
+        assert_eq!(listed(&["--min-size", "1025", "--max-size", "1M"]), ["medium.bin"]);
+        assert_eq!(listed(&["--min-filesize", "5000", "--max-filesize", "5000"]), ["medium.bin"]);
+        assert_eq!(listed(&["--min-size", "1024B", "--max-size", "3MiB"]), ["large.iso", "medium.bin", "small.txt"]);
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--min-size", "2M", "--max-size", "1M"])).is_err());
+
+        // Zero is a size like any other, only empty files are at most that large.
+        fs::write(data.join("empty.log"), "").unwrap();
+        assert_eq!(listed(&["--max-size", "0"]), ["empty.log"]);
+        assert_eq!(listed(&["--min-size", "0"]).len(), 5);
+        assert!(GenerationOpt::from_iter_safe(&["g", "--max-size", "5G"]).is_ok());
+    }
+
+    #[test]
+    fn test_interrupted_generation() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "a").unwrap();
+        let sums = dir.path().join("sums.txt");
+        fs::write(&sums, "old\n").unwrap();
+        let opts = generation_opts(&["-f", path_str(&sums), path_str(&data)]);
+
+        // Interrupted before anything was walked, the checksum file is still replaced by whole lines.
+        let err = generate_checksums_until(&opts, &Arc::new(AtomicBool::new(true)), &mut Stats::default()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<AppError>(), Some(AppError::InterruptedError(0))));
+        assert_eq!(fs::read_to_string(&sums).unwrap(), "");
+        assert!(generate_checksums(&opts).unwrap());
+        assert_eq!(fs::read_to_string(&sums).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_stats() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.bin"), vec![0u8; 3000]).unwrap();
+        fs::write(data.join("b.bin"), vec![1u8; 1234]).unwrap();
+        fs::write(data.join("empty"), "").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        let mut stats = Stats::default();
+        let opts = generation_opts(&["-f", path_str(&sums), "--stats", path_str(&data)]);
+        assert!(generate_checksums_until(&opts, &Arc::new(AtomicBool::new(false)), &mut stats).unwrap());
+        assert_eq!((stats.files, stats.bytes), (3, 4234));
+
+        let stats = Stats { files: 1234, bytes: 6 << 30, elapsed: Duration::from_secs(12) };
+        assert_eq!(stats.to_string(), "Hashed 1,234 files (6.00 GiB) in 12.0s (512.00 MiB/s)");
+        assert_eq!(Stats::default().to_string(), "Hashed 0 files (0 B) in 0.0s");
+    }
+
+    #[test]
+    fn test_assumed_algorithm() {
+        let entry = |checksum: &str, algorithm| Entry { path: PathBuf::from("a.txt"), checksum: checksum.to_owned(), size: None, algorithm };
+        let md5 = "bb057481a1b7abc93ad5d70d52e3a55f";
+        let sha1 = "a9c0f8c056a19fdfd18db386039bdc90e680116c";
+
+        assert_eq!(assumed_algorithm(&entry(md5, None), None, None, false), Some(Algorithm::MD5));
+        assert_eq!(assumed_algorithm(&entry(sha1, None), None, None, false), Some(Algorithm::SHA1));
+        // Given algorithms win, the entry's own over `-a`, unless `--mixed` lets the length decide.
+        assert_eq!(assumed_algorithm(&entry(md5, Some(Algorithm::XXH3_128)), Some(Algorithm::SHA1), None, false), Some(Algorithm::XXH3_128));
+        assert_eq!(assumed_algorithm(&entry(md5, None), Some(Algorithm::SHA1), None, false), Some(Algorithm::SHA1));
+        assert_eq!(assumed_algorithm(&entry(md5, None), Some(Algorithm::SHA1), None, true), Some(Algorithm::MD5));
+        assert_eq!(assumed_algorithm(&entry("bb057481a1", None), None, None, false), None);
+        assert_eq!(assumed_algorithm(&entry("not hex", None), None, None, false), None);
+
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+        fs::write(&sums, format!("{}  a.txt\n", md5)).unwrap();
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums), "--verbose"])).unwrap());
+        assert!(VerificationOpt::from_iter_safe(&["v", "-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn test_ambiguous_entry() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("a.txt");
+        fs::write(&file, "abcdABCD1234").unwrap();
+        let entry = |checksum: String| Entry { path: PathBuf::from("a.txt"), checksum, size: None, algorithm: None };
+        let hex = |algorithm| encode_checksum(&calculate_checksum(&file, algorithm).unwrap(), Encoding::Hex);
+
+        // Plain `md5sum` output still verifies, an XXH128 checksum without a header fails as MD5,
+        // naming XXH128 as what it might be instead.
+        assert_eq!(verify_entry(&file, &entry(hex(Algorithm::MD5)), None, None, None, false).unwrap(), Verdict::Ok);
+        assert_eq!(verify_entry(&file, &entry(hex(Algorithm::XXH3_128)), None, None, None, false).unwrap(), Verdict::Ambiguous(Algorithm::MD5, "XXH128".to_owned()));
+        assert_eq!(verify_entry(&file, &entry(hex(Algorithm::XXH3_128)), Some(Algorithm::XXH3_128), None, None, false).unwrap(), Verdict::Ok);
+
+        let sums = dir.path().join("sums.txt");
+        fs::write(&sums, format!("{}  a.txt\n", hex(Algorithm::BLAKE3))).unwrap();
+        let mut summary = Summary::default();
+        assert!(!verify_checksum_file(&verification_opts(&["-f", path_str(&sums)]), &sums, None, &mut summary).unwrap());
+        assert_eq!(summary, Summary { failed: 1, ..Summary::default() });
+        fs::write(&sums, format!("# algorithm: BLAKE3\n{}  a.txt\n", hex(Algorithm::BLAKE3))).unwrap();
+        assert!(verify_checksums(&verification_opts(&["-f", path_str(&sums)])).unwrap());
+    }
+
+    #[test]
+    fn test_json_report() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("b.txt"), "abcd").unwrap();
+        fs::write(dir.path().join("c.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+        fs::write(&sums, [
+            "bb057481a1b7abc93ad5d70d52e3a55f  a.txt",
+            "bb057481a1b7abc93ad5d70d52e3a55f  b.txt",
+            "bb057481a1b7abc93ad5d70d52e3a55f  c.txt",
+            "bb057481a1b7abc93ad5d70d52e3a55f  gone.txt",
+            "garbage",
+        ].join("\n")).unwrap();
+
+        let opts = verification_opts(&["-f", path_str(&sums), "--format", "json"]);
+        let mut summary = Summary::default();
+        assert!(!verify_checksum_file(&opts, &sums, None, &mut summary).unwrap());
+        let report: serde_json::Value = serde_json::from_str(&json_report(&summary).unwrap()).unwrap();
+        let mut ok: Vec<&str> = report["ok"].as_array().unwrap().iter().map(|p| p.as_str().unwrap()).collect();
+        ok.sort();
+        assert_eq!(ok, ["a.txt", "c.txt"]);
+        assert_eq!(report["failed"], serde_json::json!(["b.txt"]));
+        assert_eq!(report["missing"], serde_json::json!(["gone.txt"]));
+        assert_eq!(report["summary"]["ok"], 2);
+        assert_eq!(report["summary"]["failed"], 1);
+        assert_eq!(report["summary"]["missing"], 1);
+        assert_eq!(report["summary"]["malformed"], 1);
+        assert!(report["summary"].get("files").is_none());
+        // The exit status is the same as with the text output.
+        assert!(!verify_checksums(&opts).unwrap());
+        assert!(VerificationOpt::from_iter_safe(&["v", "--format", "xml"]).is_err());
+
+        // Files are only collected for the JSON report.
+        let mut summary = Summary::default();
+        verify_checksum_file(&verification_opts(&["-f", path_str(&sums)]), &sums, None, &mut summary).unwrap();
+        assert_eq!(summary.ok, 2);
+        assert!(summary.files.ok.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_permission_denied() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir(&data).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("secret.txt"), "abcdABCD1234").unwrap();
+        fs::set_permissions(data.join("secret.txt"), fs::Permissions::from_mode(0o000)).unwrap();
+        // Permissions don't stop root.
+        if fs::File::open(data.join("secret.txt")).is_ok() {
+            return;
+        }
+        let sums = dir.path().join("sums.txt");
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), path_str(&data)])).unwrap());
+        assert_eq!(fs::read_to_string(&sums).unwrap().lines().count(), 1);
+        let e = generate_checksums(&generation_opts(&["-f", path_str(&sums), "--strict", path_str(&data)])).unwrap_err();
+        assert!(matches!(e.downcast_ref::<AppError>(), Some(AppError::RunError(1))));
+        assert_eq!(fs::read_to_string(&sums).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn test_is_permission_denied() {
+        let denied = || io::Error::from(io::ErrorKind::PermissionDenied);
+        assert!(is_permission_denied(&AppError::PermissionDeniedError(PathBuf::from("a.txt")).into()));
+        assert!(is_permission_denied(&denied().into()));
+        assert!(is_permission_denied(&anyhow::Error::from(denied()).context("walking")));
+        assert!(!is_permission_denied(&AppError::ReadError(PathBuf::from("a.txt"), "broken".to_owned()).into()));
+        assert!(!is_permission_denied(&io::Error::from(io::ErrorKind::NotFound).into()));
+    }
+
+    #[test]
+    fn test_file_arguments() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(data.join("sub")).unwrap();
+        fs::write(data.join("one.iso"), "abcdABCD1234").unwrap();
+        fs::write(data.join("sub/two.iso"), "abcdABCD1234").unwrap();
+        fs::write(data.join("sub/notes.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+        let (one, two, missing) = (data.join("one.iso"), data.join("sub/two.iso"), data.join("missing.iso"));
+        let lines = || -> Vec<String> { fs::read_to_string(&sums).unwrap().lines().map(str::to_owned).collect() };
+
+        // Files are taken as they are, without walking anything, and written as given.
+        assert!(generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), path_str(&two), path_str(&one)])).unwrap());
+        assert_eq!(lines(), [
+            format!("bb057481a1b7abc93ad5d70d52e3a55f  {}", one.display()),
+            format!("bb057481a1b7abc93ad5d70d52e3a55f  {}", two.display()),
+        ]);
+        // A missing one is an error, but the others are still written.
+        let e = generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), path_str(&one), path_str(&missing), path_str(&two)])).unwrap_err();
+        assert!(matches!(e.downcast_ref::<AppError>(), Some(AppError::RunError(1))));
+        assert_eq!(lines().len(), 2);
+        // Patterns match the names of files given directly.
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--include", "*.iso", path_str(&one), &data.join("sub").display().to_string()])).unwrap());
+        assert_eq!(lines().len(), 2);
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--exclude", "one.iso", path_str(&one), path_str(&two)])).unwrap());
+        assert_eq!(lines().len(), 1);
+    }
+
+    #[test]
+    fn test_completions() {
+        let args = AppArgs::from_iter(&["chksum", "completions", "Bash"]);
+        let shell = match args.cmd {
+            Commands::Completions { shell } => shell,
+            _ => panic!("Not the completions command."),
+        };
+        let mut script = Vec::new();
+        assert!(print_completions(shell, &mut script).unwrap());
+        let script = String::from_utf8(script).unwrap();
+        assert!(script.contains("chksum"));
+        assert!(script.contains("--algorithm"));
+        assert!(AppArgs::from_iter_safe(&["chksum", "completions", "tcsh"]).is_err());
+    }
+
+    #[test]
+    fn test_path_mode() {
+        assert_eq!(output_path(Path::new("./a/b.txt"), Path::new("."), PathMode::AsGiven), Path::new("a/b.txt"));
+        assert_eq!(output_path(Path::new("/data/a/b.txt"), Path::new("/data"), PathMode::AsGiven), Path::new("/data/a/b.txt"));
+        assert_eq!(output_path(Path::new("/data/a/b.txt"), Path::new("/data"), PathMode::Relative), Path::new("a/b.txt"));
+        assert_eq!(output_path(Path::new("/data/a.iso"), Path::new("/data/a.iso"), PathMode::Relative), Path::new("a.iso"));
+        assert_eq!(output_path(Path::new("./listed.txt"), Path::new("/data"), PathMode::Relative), Path::new("listed.txt"));
+
+        let dir = tempdir().unwrap();
+        let data = dir.path().canonicalize().unwrap().join("archive");
+        fs::create_dir_all(data.join("sub")).unwrap();
+        fs::write(data.join("sub").join("a.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        // An absolute directory, with paths relative to it.
+        assert!(generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), "--path-mode", "relative", path_str(&data)])).unwrap());
+        assert_eq!(fs::read_to_string(&sums).unwrap(), format!("bb057481a1b7abc93ad5d70d52e3a55f  {}\n", Path::new("sub").join("a.txt").display()));
+        // Still good once the tree is mounted elsewhere, given where with `-d`.
+        let mounted = dir.path().join("mnt");
+        fs::create_dir(&mounted).unwrap();
+        fs::rename(&data, mounted.join("archive")).unwrap();
+        let mut summary = Summary::default();
+        let opts = verification_opts(&["-f", path_str(&sums), "-d", &mounted.join("archive").display().to_string()]);
+        assert!(verify_checksum_file(&opts, &sums, None, &mut summary).unwrap());
+        assert_eq!(summary, Summary { ok: 1, ..Summary::default() });
+
+        let data = mounted.join("archive");
+        assert!(generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), "--path-mode", "absolute", path_str(&data)])).unwrap());
+        assert_eq!(fs::read_to_string(&sums).unwrap(), format!("bb057481a1b7abc93ad5d70d52e3a55f  {}\n", data.join("sub").join("a.txt").display()));
+
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--path-mode", "relative", path_str(&data), path_str(&mounted)])).is_err());
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--path-mode", "relative", "--resume", path_str(&data)])).is_err());
+        assert!(GenerationOpt::from_iter_safe(&["g", "--path-mode", "canonical"]).is_err());
+    }
+
+    #[test]
+    fn test_strip_prefix() {
+        assert_eq!(output_path(Path::new("./sub/a.txt"), Path::new("sub"), PathMode::Relative), Path::new("a.txt"));
+        assert_eq!(output_path(Path::new("/srv/releases/v1.2/a.iso"), Path::new("/srv/releases/v1.2/"), PathMode::Relative), Path::new("a.iso"));
+
+        let dir = tempdir().unwrap();
+        let (releases, other) = (dir.path().join("releases"), dir.path().join("other"));
+        fs::create_dir_all(releases.join("v1.2")).unwrap();
+        fs::create_dir_all(&other).unwrap();
+        fs::write(releases.join("v1.2").join("a.iso"), "abcdABCD1234").unwrap();
+        fs::write(other.join("b.iso"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        // Files outside of the prefix keep their whole path.
+        let prefix = releases.display().to_string() + "/";
+        assert!(generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), "--strip-prefix", &prefix, path_str(&releases), path_str(&other)])).unwrap());
+        assert_eq!(fs::read_to_string(&sums).unwrap(), format!(
+            "bb057481a1b7abc93ad5d70d52e3a55f  {}\nbb057481a1b7abc93ad5d70d52e3a55f  {}\n",
+            other.join("b.iso").display(), Path::new("v1.2").join("a.iso").display(),
+        ));
+        assert!(GenerationOpt::from_iter_safe(&["g", "--strip-prefix", "a", "--path-mode", "absolute"]).is_err());
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--strip-prefix", &prefix, "--per-directory", path_str(&releases)])).is_err());
+    }
+
+    #[test]
+    fn test_multiple_roots() {
+        let dir = tempdir().unwrap();
+        let (a, b) = (dir.path().join("a"), dir.path().join("b"));
+        fs::create_dir_all(a.join("sub")).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        fs::write(a.join("sub").join("one.txt"), "abcdABCD1234").unwrap();
+        fs::write(b.join("two.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+        let expected = [a.join("sub").join("one.txt"), b.join("two.txt")]
+            .iter().map(|p| format!("bb057481a1b7abc93ad5d70d52e3a55f  {}\n", p.display())).collect::<String>();
+
+        // Repeated `-d`, or mixed with directories after the options, make one checksum file.
+        assert!(generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), "-d", path_str(&a), "-d", path_str(&b)])).unwrap());
+        assert_eq!(fs::read_to_string(&sums).unwrap(), expected);
+        assert!(generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), "-d", path_str(&b), path_str(&a)])).unwrap());
+        assert_eq!(fs::read_to_string(&sums).unwrap(), expected);
+        let mut summary = Summary::default();
+        assert!(verify_checksum_file(&verification_opts(&["-f", path_str(&sums)]), &sums, None, &mut summary).unwrap());
+        assert_eq!(summary, Summary { ok: 2, ..Summary::default() });
+
+        // A file reachable from both is listed under each.
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(a.join("sub"), b.join("linked")).unwrap();
+            assert!(generate_checksums(&generation_opts(&["-a", "md5", "-f", path_str(&sums), "-d", path_str(&a), "-d", path_str(&b)])).unwrap());
+            let content = fs::read_to_string(&sums).unwrap();
+            assert_eq!(content.lines().count(), 3);
+            assert!(content.contains(&b.join("linked").join("one.txt").display().to_string()));
+        }
+
+        // Which of the directories a relative path would be under is unknown.
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--path-mode", "relative", "-d", path_str(&a), "-d", path_str(&b)])).is_err());
+        assert_eq!(generation_opts(&[]).roots(), [PathBuf::from(".")]);
+    }
+
+    #[test]
+    fn test_list_algorithms() {
+        let mut listing = Vec::new();
+        assert!(list_algorithms(&mut listing).unwrap());
+        let listing = String::from_utf8(listing).unwrap();
+        let lines: Vec<&str> = listing.lines().skip(1).collect();
+        assert_eq!(lines.len(), ALGORITHMS.len());
+        for (line, info) in lines.iter().zip(ALGORITHMS) {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            assert_eq!(columns[0], info.name);
+            assert_eq!(columns[1], (info.algorithm.digest_size() * 8).to_string());
+        }
+        assert!(listing.contains("SHA512/256    256  no       sha512/256, sha-512/256, sha512_256\n"));
+        assert!(matches!(AppArgs::from_iter(&["chksum", "list-algorithms"]).cmd, Commands::Algorithms));
+    }
+
+    #[test]
+    fn test_multiple_checksum_files() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("b.txt"), "abcdABCD1234").unwrap();
+        let (md5sums, sha256sums) = (dir.path().join("md5sums.txt"), dir.path().join("sha256sums.txt"));
+        // MD5 guessed from the length, and SHA-256 named by the tag.
+        fs::write(&md5sums, "bb057481a1b7abc93ad5d70d52e3a55f  a.txt\nbb057481a1b7abc93ad5d70d52e3a55f  b.txt\n").unwrap();
+        fs::write(&sha256sums, format!("SHA256 (a.txt) = {}\n", "0".repeat(64))).unwrap();
+        let verify = |args: &[&str]| verify_checksums(&verification_opts(&[&["-f", path_str(&md5sums), "-f", path_str(&sha256sums)], args].concat()));
+
+        // Both files are verified, a mismatch in either fails the run.
+        assert!(!verify(&[]).unwrap());
+        fs::write(&sha256sums, "SHA256 (a.txt) = 423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b\n").unwrap();
+        assert!(verify(&[]).unwrap());
+        assert!(verify(&["--check-extra", path_str(dir.path())]).unwrap());
+        fs::write(dir.path().join("c.txt"), "abcdABCD1234").unwrap();
+        assert!(!verify(&["--check-extra", path_str(dir.path()), "--strict-extra"]).unwrap());
+
+        // A single one still names the file to look for in each directory.
+        assert!(verify(&["--per-directory"]).is_err());
+    }
+
+    #[test]
+    fn test_tag_algorithm() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(&data).unwrap();
+        fs::write(data.join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(data.join("b.txt"), "abcdABCD1234").unwrap();
+        let sums = dir.path().join("sums.txt");
+
+        // SHA-256 and BLAKE3 checksums have the same length, the column tells them apart.
+        assert!(generate_checksums(&generation_opts(&["-a", "sha256,blake3", "--tag-algorithm", "-f", path_str(&sums), path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "# columns: algorithm checksum path");
+        assert!(lines[1].starts_with("SHA256 423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b  "));
+        assert!(lines[3].starts_with("BLAKE3 "));
+        let mut summary = Summary::default();
+        assert!(verify_checksum_file(&verification_opts(&["-f", path_str(&sums)]), &sums, None, &mut summary).unwrap());
+        assert_eq!(summary, Summary { ok: 4, ..Summary::default() });
+        let mut summary = Summary::default();
+        assert!(verify_checksum_file(&verification_opts(&["-f", path_str(&sums), "-a", "blake3"]), &sums, None, &mut summary).unwrap());
+        assert_eq!(summary, Summary { ok: 2, ..Summary::default() });
+
+        assert!(generate_checksums(&generation_opts(&["--tag-algorithm", "--format", "csv", "-f", path_str(&sums), path_str(&data)])).is_err());
+        assert!(GenerationOpt::from_iter_safe(&["g", "--tag-algorithm", "--with-size"]).is_err());
+    }
+
+    #[test]
+    fn test_exclude_extensions() {
+        let dir = tempdir().unwrap();
+        let data = dir.path().join("data");
+        fs::create_dir_all(&data).unwrap();
+        for name in ["a.txt", "b.TMP", "c.part", "d.Swp"] {
+            fs::write(data.join(name), "abcdABCD1234").unwrap();
+        }
+        let sums = dir.path().join("sums.txt");
+        assert!(generate_checksums(&generation_opts(&["-f", path_str(&sums), "--exclude-ext", "tmp,.PART", "--exclude-ext", "swp", path_str(&data)])).unwrap());
+        let content = fs::read_to_string(&sums).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert!(content.ends_with("a.txt\n"));
+    }
+
 }
@@ -0,0 +1,814 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use anyhow::Result;
+use itertools::{join, Itertools};
+use serde::Serialize;
+use chksum::{AppError, Algorithm, Encoding, FileChecksums, decode_checksum, encode_checksum};
+
+const HASHDEEP_MAGIC: &str = "%%%% HASHDEEP-1.0";
+const HASHDEEP_HEADER_PREFIX: &str = "%%%% ";
+const HASHDEEP_COMMENT_PREFIX: &str = "##";
+const COMMENT_PREFIX: &str = "#";
+const ALGORITHM_HEADER: &str = "# algorithm: ";
+/// Marks GNU style files whose lines have the file size between the checksum and the path.
+const SIZE_HEADER: &str = "# columns: checksum size path";
+/// Marks GNU style files whose lines start with the algorithm, followed by a space.
+const ALGORITHM_COLUMN_HEADER: &str = "# columns: algorithm checksum path";
+/// Marks files whose checksums are HMACs, which only verify with the same `--hmac-key`.
+const KEYED_HEADER: &str = "# keyed: HMAC";
+const CSV_COLUMNS: &[&str] = &["path", "algorithm", "hash", "size_bytes", "mtime"];
+/// Leading columns of the `--update` index, followed by one column per algorithm.
+const INDEX_COLUMNS: &[&str] = &["path", "size_bytes", "mtime"];
+/// Byte order mark some Windows editors put at the start of UTF-8 files.
+const BOM: char = '\u{feff}';
+
+/// A single record read from a checksum file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub checksum: String,
+    pub size: Option<u64>,
+    pub algorithm: Option<Algorithm>,
+}
+
+/// Entries read from a checksum file, each with the number of the line it starts on. Malformed
+/// records are `AppError`s, other errors mean the input couldn't be read.
+pub type Entries = Box<dyn Iterator<Item = (u64, Result<Option<Entry>>)>>;
+
+/// One checksum of a file in the JSON output formats.
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    path: Cow<'a, str>,
+    /// The path's raw bytes in base64, only when it isn't valid UTF-8 and `path` is lossy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path_bytes: Option<String>,
+    algorithm: String,
+    hash: &'a str,
+    size: u64,
+}
+
+/// A generated record, with one checksum per requested algorithm.
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub path: PathBuf,
+    pub checksums: Vec<String>,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Writes checksum records, each terminated by either a newline or a NUL character.
+pub struct Writer {
+    output: Box<dyn Write>,
+    terminator: char,
+    mode: char,
+}
+
+impl Writer {
+    /// `binary` marks GNU style lines with `*` in front of the path, as `sha256sum -b` does.
+    pub fn new(output: Box<dyn Write>, zero: bool, binary: bool) -> Self {
+        Self {
+            output,
+            terminator: if zero { '\0' } else { '\n' },
+            mode: if binary { '*' } else { ' ' },
+        }
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.output.flush()?;
+        Ok(())
+    }
+
+    pub fn write_record(&mut self, record: &str) -> Result<()> {
+        write!(self.output, "{}{}", record, self.terminator)?;
+        Ok(())
+    }
+
+    /// Escapes the path the way coreutils does, returning whether the line needs the `\\` prefix.
+    /// Nothing needs escaping when records are NUL-terminated.
+    fn escape_path(&self, path: &Path) -> (&'static str, String) {
+        let path = path.display().to_string();
+        if self.terminator == '\0' || !path.contains(&['\\', '\n', '\r'][..]) {
+            return ("", path);
+        }
+        ("\\", path.replace('\\', "\\\\").replace('\n', "\\n").replace('\r', "\\r"))
+    }
+
+    /// Writes comment lines naming the tool version, the algorithms and the generation time.
+    pub fn write_header(&mut self, algorithms: &[Algorithm], keyed: bool) -> Result<()> {
+        self.write_record(&format!("# chksum v{}", env!("CARGO_PKG_VERSION")))?;
+        self.write_record(&format!("{}{}", ALGORITHM_HEADER, algorithms.iter().join(",")))?;
+        if keyed {
+            self.write_record(KEYED_HEADER)?;
+        }
+        self.write_record(&format!("# generated: {}", humantime::format_rfc3339_seconds(SystemTime::now())))
+    }
+
+    pub fn write_hashdeep_header(&mut self, algorithms: &[Algorithm]) -> Result<()> {
+        let cwd = std::env::current_dir()?;
+        let args: Vec<String> = std::env::args().collect();
+        let names = join(algorithms.iter().map(|a| a.to_string().to_lowercase()), ",");
+        self.write_record(HASHDEEP_MAGIC)?;
+        self.write_record(&format!("{}size,{},filename", HASHDEEP_HEADER_PREFIX, names))?;
+        self.write_record(&format!("## Invoked from: {}", cwd.display()))?;
+        self.write_record(&format!("## $ {}", args.join(" ")))?;
+        self.write_record("##")
+    }
+
+    /// Writes a hashdeep line, i.e. `size,checksum,...,path`, with names escaped as in GNU style
+    /// lines, which hashdeep itself doesn't do.
+    pub fn write_hashdeep_entry(&mut self, path: &Path, checksums: &[String], size: u64) -> Result<()> {
+        let (prefix, path) = self.escape_path(path);
+        self.write_record(&format!("{}{},{},{}", prefix, size, checksums.join(","), path))
+    }
+
+    /// Writes a BSD style line, i.e. `SHA256 (path) = checksum`, as `sha256sum --tag` does.
+    pub fn write_tagged_entry(&mut self, path: &Path, checksum: &str, algorithm: Algorithm) -> Result<()> {
+        let (prefix, path) = self.escape_path(path);
+        self.write_record(&format!("{}{} ({}) = {}", prefix, algorithm, path, checksum))
+    }
+
+    /// Writes one object per checksum, as a JSON array, or with `lines` one object per line.
+    pub fn write_json_entries(&mut self, records: &[Record], algorithms: &[Algorithm], lines: bool) -> Result<()> {
+        let entries = records.iter().flat_map(|record| {
+            let path_bytes = match record.path.to_str() {
+                Some(_) => None,
+                None => Some(base64::encode(record.path.as_os_str().as_encoded_bytes())),
+            };
+            algorithms.iter().zip(record.checksums.iter()).map(move |(algorithm, checksum)| JsonEntry {
+                path: record.path.to_string_lossy(),
+                path_bytes: path_bytes.clone(),
+                algorithm: algorithm.to_string().to_lowercase(),
+                hash: checksum,
+                size: record.size,
+            })
+        });
+        if lines {
+            for entry in entries {
+                self.write_record(&serde_json::to_string(&entry)?)?;
+            }
+            return Ok(());
+        }
+        let entries: Vec<JsonEntry> = entries.collect();
+        self.write_record(&serde_json::to_string_pretty(&entries)?)
+    }
+
+    pub fn write_csv_entries(&mut self, records: &[Record], algorithms: &[Algorithm]) -> Result<()> {
+        let mut writer = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::Any(self.terminator as u8))
+            .from_writer(&mut self.output);
+        writer.write_record(CSV_COLUMNS)?;
+        for record in records.iter() {
+            let path = record.path.to_string_lossy();
+            let size = record.size.to_string();
+            let modified = humantime::format_rfc3339_seconds(record.modified).to_string();
+            for (algorithm, checksum) in algorithms.iter().zip(record.checksums.iter()) {
+                writer.write_record([&*path, &algorithm.to_string(), checksum, &size, &modified])?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write_size_header(&mut self) -> Result<()> {
+        self.write_record(SIZE_HEADER)
+    }
+
+    /// Marks the file as having the algorithm in front of every GNU style line.
+    pub fn write_algorithm_column_header(&mut self) -> Result<()> {
+        self.write_record(ALGORITHM_COLUMN_HEADER)
+    }
+
+    /// Writes a GNU style line with the algorithm in front, i.e. `algorithm checksum  path`.
+    pub fn write_algorithm_gnu_entry(&mut self, path: &Path, checksum: &str, algorithm: Algorithm) -> Result<()> {
+        let (prefix, path) = self.escape_path(path);
+        self.write_record(&format!("{}{} {} {}{}", prefix, algorithm, checksum, self.mode, path))
+    }
+
+    /// Writes a GNU style line with the size in front of the path, i.e. `checksum  size  path`.
+    pub fn write_sized_gnu_entry(&mut self, path: &Path, checksum: &str, size: u64) -> Result<()> {
+        let (prefix, path) = self.escape_path(path);
+        self.write_record(&format!("{}{}  {} {}{}", prefix, checksum, size, self.mode, path))
+    }
+
+    pub fn write_gnu_entry(&mut self, path: &Path, checksum: &str) -> Result<()> {
+        let (prefix, path) = self.escape_path(path);
+        self.write_record(&format!("{}{} {}{}", prefix, checksum, self.mode, path))
+    }
+}
+
+/// A file written under a temporary name next to its destination and renamed over it once
+/// committed, so an interrupted run leaves the previous file intact. The temporary file is removed
+/// if never committed.
+pub struct PendingFile {
+    temp: PathBuf,
+    path: PathBuf,
+}
+
+impl PendingFile {
+    pub fn create(path: &Path) -> Result<(Self, File)> {
+        let name = format!(".{}.{}.tmp", path.file_name().unwrap_or_default().to_string_lossy(), std::process::id());
+        let temp = path.with_file_name(name);
+        let file = OpenOptions::new().write(true).create_new(true).open(&temp)?;
+        // The replacement keeps the permissions of the file it replaces.
+        if let Ok(metadata) = path.metadata() {
+            file.set_permissions(metadata.permissions())?;
+        }
+        Ok((Self { temp, path: path.to_owned() }, file))
+    }
+
+    pub fn temp_path(&self) -> &Path {
+        &self.temp
+    }
+
+    pub fn commit(self) -> Result<()> {
+        fs::rename(&self.temp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl Drop for PendingFile {
+    fn drop(&mut self) {
+        // Nothing is left to remove once committed.
+        let _ = fs::remove_file(&self.temp);
+    }
+}
+
+struct CsvColumns {
+    path: usize,
+    hash: usize,
+    algorithm: Option<usize>,
+    size: Option<usize>,
+}
+
+/// Reads entries from a CSV file as written with `--output-format csv`, only the `path` and `hash`
+/// columns are required.
+pub fn read_csv_entries(input: Box<dyn Read>, algorithm: Option<Algorithm>, zero: bool) -> Result<Entries> {
+    let mut input = BufReader::new(input);
+    check_not_utf16(&mut input)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .terminator(if zero { csv::Terminator::Any(b'\0') } else { csv::Terminator::CRLF })
+        .from_reader(input);
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+    let columns = match (column("path"), column("hash")) {
+        (Some(path), Some(hash)) => CsvColumns { path, hash, algorithm: column("algorithm"), size: column("size_bytes") },
+        _ => return Err(AppError::InvalidLineError(headers.iter().join(",")).into()),
+    };
+    Ok(Box::new(reader.into_records().map(move |row| {
+        let line = match &row {
+            Ok(row) => row.position().map_or(0, |p| p.line()),
+            Err(e) => e.position().map_or(0, |p| p.line()),
+        };
+        (line, parse_csv_row(row, &columns, algorithm))
+    })))
+}
+
+fn parse_csv_row(row: csv::Result<csv::StringRecord>, columns: &CsvColumns, algorithm: Option<Algorithm>) -> Result<Option<Entry>> {
+    let row = match row {
+        Ok(row) => row,
+        Err(e) if e.is_io_error() => return Err(e.into()),
+        Err(e) => return Err(AppError::InvalidLineError(e.to_string()).into()),
+    };
+    let invalid = || AppError::InvalidLineError(row.iter().join(","));
+    let row_algorithm = match columns.algorithm.and_then(|c| row.get(c)) {
+        Some(name) => Some(name.parse::<Algorithm>()?),
+        None => None,
+    };
+    // Rows for other algorithms are skipped when the algorithm is given explicitly.
+    if algorithm.is_some() && row_algorithm.is_some() && algorithm != row_algorithm {
+        return Ok(None);
+    }
+    Ok(Some(Entry {
+        path: PathBuf::from(row.get(columns.path).ok_or_else(invalid)?),
+        checksum: row.get(columns.hash).ok_or_else(invalid)?.to_owned(),
+        size: match columns.size.and_then(|c| row.get(c)) {
+            Some(size) => Some(size.parse::<u64>().map_err(|_| invalid())?),
+            None => None,
+        },
+        algorithm: row_algorithm.or(algorithm),
+    }))
+}
+
+fn index_headers(algorithms: &[Algorithm]) -> Vec<String> {
+    INDEX_COLUMNS.iter().map(|c| c.to_string()).chain(algorithms.iter().map(Algorithm::to_string)).collect()
+}
+
+/// Writes the index kept next to the checksum file by `--update`, with the size, the modification
+/// time to the nanosecond and the raw checksums of each file, by path as walked.
+pub fn write_index(output: impl Write, files: &[FileChecksums], algorithms: &[Algorithm]) -> Result<()> {
+    let mut writer = csv::Writer::from_writer(output);
+    writer.write_record(index_headers(algorithms))?;
+    for file in files.iter() {
+        let mut row = vec![
+            file.path.to_string_lossy().into_owned(),
+            file.size.to_string(),
+            humantime::format_rfc3339_nanos(file.modified).to_string(),
+        ];
+        row.extend(file.checksums.iter().map(|c| encode_checksum(c, Encoding::Hex)));
+        writer.write_record(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads an index written by `write_index`. One written for other algorithms is of no use, and
+/// reads as empty.
+pub fn read_index(input: impl Read, algorithms: &[Algorithm]) -> Result<HashMap<PathBuf, FileChecksums>> {
+    let mut reader = csv::Reader::from_reader(input);
+    let mut index = HashMap::new();
+    if reader.headers()?.iter().ne(index_headers(algorithms).iter().map(String::as_str)) {
+        return Ok(index);
+    }
+    for row in reader.into_records() {
+        let row = row?;
+        let invalid = || AppError::InvalidLineError(row.iter().join(","));
+        let path = PathBuf::from(&row[0]);
+        let file = FileChecksums {
+            path: path.clone(),
+            checksums: row.iter().skip(INDEX_COLUMNS.len()).map(|c| decode_checksum(c, Some(Encoding::Hex))).collect::<Result<_>>()?,
+            size: row[1].parse().map_err(|_| invalid())?,
+            modified: humantime::parse_rfc3339(&row[2]).map_err(|_| invalid())?,
+        };
+        index.insert(path, file);
+    }
+    Ok(index)
+}
+
+/// Fails on a UTF-16 byte order mark, as PowerShell writes by default, which would otherwise only
+/// give a confusing error about invalid UTF-8.
+fn check_not_utf16(reader: &mut impl BufRead) -> io::Result<()> {
+    let start = reader.fill_buf()?;
+    if start.starts_with(&[0xff, 0xfe]) || start.starts_with(&[0xfe, 0xff]) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, AppError::Utf16Error));
+    }
+    Ok(())
+}
+
+/// Splits the input into records on newlines, or on NUL characters when `zero` is set. Files written
+/// on Windows are read the same, `\r\n` ends a line too and a leading UTF-8 BOM is dropped.
+pub fn read_records(input: Box<dyn Read>, zero: bool) -> Box<dyn Iterator<Item = io::Result<String>>> {
+    let mut reader = BufReader::new(input);
+    if let Err(e) = check_not_utf16(&mut reader) {
+        return Box::new(std::iter::once(Err(e)));
+    }
+    let records: Box<dyn Iterator<Item = io::Result<String>>> = if zero {
+        Box::new(reader.split(b'\0').map(|record| {
+            record.and_then(|r| String::from_utf8(r).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+        }))
+    } else {
+        // Also strips `\r`, which can't be part of a name as those are written escaped.
+        Box::new(reader.lines())
+    };
+    let mut first = true;
+    Box::new(records.map(move |record| record.map(|mut r| {
+        if std::mem::take(&mut first) && r.starts_with(BOM) {
+            r.remove(0);
+        }
+        r
+    })))
+}
+
+#[derive(Debug)]
+struct HashdeepColumns {
+    count: usize,
+    size: usize,
+    hash: usize,
+    algorithm: Algorithm,
+}
+
+/// Parses checksum files line by line, switching to hashdeep mode when the file starts with the
+/// hashdeep magic line.
+#[derive(Debug)]
+pub struct Parser {
+    algorithm: Option<Algorithm>,
+    zero: bool,
+    first_line: bool,
+    hashdeep: Option<Option<HashdeepColumns>>,
+    /// Set by the size header, see `SIZE_HEADER`.
+    sized: bool,
+    /// Set by the algorithm column header, see `ALGORITHM_COLUMN_HEADER`.
+    algorithm_column: bool,
+}
+
+impl Parser {
+    pub fn new(algorithm: Option<Algorithm>, zero: bool) -> Self {
+        Self {
+            algorithm,
+            zero,
+            first_line: true,
+            hashdeep: None,
+            sized: false,
+            algorithm_column: false,
+        }
+    }
+
+    /// Returns `Ok(None)` for header and comment lines, which start with `#` outside hashdeep files.
+    pub fn parse_line(&mut self, line: &str) -> Result<Option<Entry>> {
+        // Blank lines separate hand-edited files, and don't count as the first line either.
+        if line.trim().is_empty() {
+            return Ok(None);
+        }
+        let first_line = std::mem::replace(&mut self.first_line, false);
+        if first_line && line == HASHDEEP_MAGIC {
+            self.hashdeep = Some(None);
+            return Ok(None);
+        }
+        match &self.hashdeep {
+            None if line.trim_start().starts_with(COMMENT_PREFIX) => {
+                self.parse_comment(line);
+                Ok(None)
+            }
+            None => {
+                // coreutils prefixes the line with a backslash when the file name is escaped.
+                let (escaped, line) = match line.strip_prefix('\\') {
+                    Some(rest) if !self.zero => (true, rest),
+                    _ => (false, line),
+                };
+                if self.algorithm_column {
+                    return self.parse_algorithm_gnu_line(line, escaped);
+                }
+                match self.parse_tagged_line(line, escaped) {
+                    Some(result) => result,
+                    None => self.parse_gnu_line(line, escaped).map(Some),
+                }
+            }
+            Some(_) if line.starts_with(HASHDEEP_COMMENT_PREFIX) => Ok(None),
+            Some(_) if line.starts_with(HASHDEEP_HEADER_PREFIX) => {
+                self.hashdeep = Some(Some(self.parse_hashdeep_header(line)?));
+                Ok(None)
+            }
+            Some(None) => Err(AppError::InvalidLineError(line.to_owned()).into()),
+            Some(Some(columns)) => {
+                let (escaped, line) = match line.strip_prefix('\\') {
+                    Some(line) => (true, line),
+                    None => (false, line),
+                };
+                Self::parse_hashdeep_line(columns, line, escaped).map(Some)
+            }
+        }
+    }
+
+    /// An algorithm header applies to the lines after it, unless the algorithm was given
+    /// explicitly. Headers listing several algorithms only precede tagged lines and are ignored.
+    fn parse_comment(&mut self, line: &str) {
+        if line.trim() == SIZE_HEADER {
+            self.sized = true;
+            return;
+        }
+        if line.trim() == ALGORITHM_COLUMN_HEADER {
+            self.algorithm_column = true;
+            return;
+        }
+        if self.algorithm.is_some() {
+            return;
+        }
+        if let Some(algorithm) = line.strip_prefix(ALGORITHM_HEADER).and_then(|a| a.trim().parse::<Algorithm>().ok()) {
+            self.algorithm = Some(algorithm);
+        }
+    }
+
+    fn parse_tagged_line(&self, line: &str, escaped: bool) -> Option<Result<Option<Entry>>> {
+        let (name, rest) = line.split_at(line.find(" (")?);
+        let algorithm = name.parse::<Algorithm>().ok()?;
+        let (path, checksum) = rest[2..].rsplit_once(") = ")?;
+        // Tagged lines for other algorithms are skipped when the algorithm is given explicitly.
+        if self.algorithm.is_some_and(|a| a != algorithm) {
+            return Some(Ok(None));
+        }
+        Some(Self::parse_path(line, path, escaped).map(|path| Some(Entry {
+            path,
+            checksum: checksum.to_owned(),
+            size: None,
+            algorithm: Some(algorithm),
+        })))
+    }
+
+    /// The algorithm is named on the line, so nothing is guessed. Like tagged lines, those of other
+    /// algorithms are skipped when one is given.
+    fn parse_algorithm_gnu_line(&self, line: &str, escaped: bool) -> Result<Option<Entry>> {
+        let (algorithm, rest) = line.split_once(' ')
+            .and_then(|(name, rest)| Some((name.parse::<Algorithm>().ok()?, rest)))
+            .ok_or_else(|| AppError::InvalidLineError(line.to_owned()))?;
+        if self.algorithm.is_some_and(|a| a != algorithm) {
+            return Ok(None);
+        }
+        let entry = self.parse_gnu_line(rest, escaped).map_err(|_| AppError::InvalidLineError(line.to_owned()))?;
+        Ok(Some(Entry { algorithm: Some(algorithm), ..entry }))
+    }
+
+    fn parse_gnu_line(&self, line: &str, escaped: bool) -> Result<Entry> {
+        // The path is everything after the separator, which is a space followed by the mode, i.e. a
+        // space for text or `*` for binary, so leading and trailing whitespace in file names is
+        // preserved. A single space without the mode is accepted for legacy files.
+        let (checksum, path) = line.split_once(char::is_whitespace)
+            .map(|(checksum, rest)| (checksum, rest.strip_prefix(&[' ', '*'][..]).unwrap_or(rest)))
+            .filter(|(checksum, path)| !checksum.is_empty() && !path.is_empty())
+            .ok_or_else(|| AppError::InvalidLineError(line.to_owned()))?;
+        // The size is separated from the path the same way, the mode going with the path.
+        let (size, path) = match self.sized {
+            true => path.split_once(' ')
+                .and_then(|(size, rest)| Some((Some(size.parse::<u64>().ok()?), rest.strip_prefix(&[' ', '*'][..])?)))
+                .filter(|(_, path)| !path.is_empty())
+                .ok_or_else(|| AppError::InvalidLineError(line.to_owned()))?,
+            false => (None, path),
+        };
+        Ok(Entry {
+            path: Self::parse_path(line, path, escaped)?,
+            checksum: checksum.to_owned(),
+            size,
+            algorithm: self.algorithm,
+        })
+    }
+
+    fn parse_path(line: &str, path: &str, escaped: bool) -> Result<PathBuf> {
+        if !escaped {
+            return Ok(PathBuf::from(path));
+        }
+        let mut unescaped = String::with_capacity(path.len());
+        let mut chars = path.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                unescaped.push(c);
+                continue;
+            }
+            unescaped.push(match chars.next() {
+                Some('\\') => '\\',
+                Some('n') => '\n',
+                Some('r') => '\r',
+                _ => return Err(AppError::InvalidLineError(line.to_owned()).into()),
+            });
+        }
+        Ok(PathBuf::from(unescaped))
+    }
+
+    fn parse_hashdeep_header(&self, line: &str) -> Result<HashdeepColumns> {
+        let names: Vec<&str> = line[HASHDEEP_HEADER_PREFIX.len()..].split(',').map(str::trim).collect();
+        let size = names.iter().position(|n| *n == "size");
+        let filename = names.iter().position(|n| *n == "filename");
+        let hashes: Vec<(usize, Algorithm)> = names.iter()
+            .enumerate()
+            .filter_map(|(idx, n)| n.parse::<Algorithm>().ok().map(|a| (idx, a)))
+            .collect();
+        // Prefer the column matching the requested algorithm, otherwise use the first one.
+        let hash = match self.algorithm {
+            Some(algorithm) => hashes.iter().find(|(_, a)| *a == algorithm),
+            None => hashes.first(),
+        };
+        match (size, filename, hash) {
+            (Some(size), Some(filename), Some(&(hash, algorithm))) if filename == names.len() - 1 => {
+                Ok(HashdeepColumns { count: names.len(), size, hash, algorithm })
+            }
+            _ => Err(AppError::InvalidLineError(line.to_owned()).into()),
+        }
+    }
+
+    fn parse_hashdeep_line(columns: &HashdeepColumns, line: &str, escaped: bool) -> Result<Entry> {
+        // The file name is the last column and may itself contain commas.
+        let fields: Vec<&str> = line.splitn(columns.count, ',').collect();
+        if fields.len() != columns.count {
+            return Err(AppError::InvalidLineError(line.to_owned()).into());
+        }
+        let size = fields[columns.size].parse::<u64>().or(Err(AppError::InvalidLineError(line.to_owned())))?;
+        Ok(Entry {
+            path: Self::parse_path(line, fields[columns.count - 1], escaped)?,
+            checksum: fields[columns.hash].to_owned(),
+            size: Some(size),
+            algorithm: Some(columns.algorithm),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use chksum::Algorithm;
+    use crate::manifest::{Parser, read_records};
+
+    #[test]
+    fn test_parse_gnu() {
+        let mut parser = Parser::new(None, false);
+        let entry = parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f  a/b.txt").unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("a/b.txt"));
+        assert_eq!(entry.checksum, "bb057481a1b7abc93ad5d70d52e3a55f");
+        assert_eq!(entry.size, None);
+        assert!(parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f").is_err());
+    }
+
+    #[test]
+    fn test_parse_spaces() {
+        let mut parser = Parser::new(None, false);
+        let entry = parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f  my file.txt").unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("my file.txt"));
+        // Legacy files separate the checksum and the path by a single space or a tab.
+        let entry = parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f my file.txt").unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("my file.txt"));
+        let entry = parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f\tmy file.txt").unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("my file.txt"));
+        assert_eq!(entry.checksum, "bb057481a1b7abc93ad5d70d52e3a55f");
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let mut parser = Parser::new(None, false);
+        assert!(parser.parse_line("# chksum v0.1.0").unwrap().is_none());
+        assert!(parser.parse_line("# algorithm: BLAKE2s").unwrap().is_none());
+        assert!(parser.parse_line("# generated: 2021-03-12T20:33:27Z").unwrap().is_none());
+        let entry = parser.parse_line("508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982  abc.txt").unwrap().unwrap();
+        assert_eq!(entry.algorithm, Some(Algorithm::BLAKE2S));
+
+        // An explicit algorithm wins over the header.
+        let mut parser = Parser::new(Some(Algorithm::SHA256), false);
+        parser.parse_line("# algorithm: BLAKE2s").unwrap();
+        let entry = parser.parse_line("508c5e8c327c14e2e1a72ba34eeb452f37458b209ed63a294d999b4c86675982  abc.txt").unwrap().unwrap();
+        assert_eq!(entry.algorithm, Some(Algorithm::SHA256));
+
+        let mut parser = Parser::new(None, false);
+        parser.parse_line("# algorithm: SHA256,MD5").unwrap();
+        let entry = parser.parse_line("MD5 (a.txt) = bb057481a1b7abc93ad5d70d52e3a55f").unwrap().unwrap();
+        assert_eq!(entry.algorithm, Some(Algorithm::MD5));
+    }
+
+    #[test]
+    fn test_parse_sizes() {
+        let mut parser = Parser::new(None, false);
+        parser.parse_line("# columns: checksum size path").unwrap();
+        let entry = parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f  12  a  b.txt").unwrap().unwrap();
+        assert_eq!((entry.size, entry.path), (Some(12), PathBuf::from("a  b.txt")));
+        let entry = parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f  0 *a.txt").unwrap().unwrap();
+        assert_eq!((entry.size, entry.path), (Some(0), PathBuf::from("a.txt")));
+        assert!(parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f  a.txt").is_err());
+        assert!(parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f  12 ").is_err());
+
+        // Without the header, a leading number is part of the path.
+        let mut parser = Parser::new(None, false);
+        let entry = parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f  12  a.txt").unwrap().unwrap();
+        assert_eq!((entry.size, entry.path), (None, PathBuf::from("12  a.txt")));
+    }
+
+    #[test]
+    fn test_parse_algorithm_column() {
+        let mut parser = Parser::new(None, false);
+        parser.parse_line("# columns: algorithm checksum path").unwrap();
+        let entry = parser.parse_line("BLAKE2s 1234  a  b.txt").unwrap().unwrap();
+        assert_eq!((entry.algorithm, entry.checksum.as_str(), entry.path), (Some(Algorithm::BLAKE2S), "1234", PathBuf::from("a  b.txt")));
+        let entry = parser.parse_line("\\SHA512/256 1234 *a\\nb.txt").unwrap().unwrap();
+        assert_eq!((entry.algorithm, entry.path), (Some(Algorithm::SHA512_256), PathBuf::from("a\nb.txt")));
+        assert!(parser.parse_line("1234  a.txt").is_err());
+        assert!(parser.parse_line("MD5 1234").is_err());
+
+        // Lines of other algorithms are skipped when one is given.
+        let mut parser = Parser::new(Some(Algorithm::MD5), false);
+        parser.parse_line("# columns: algorithm checksum path").unwrap();
+        assert_eq!(parser.parse_line("SHA256 1234  a.txt").unwrap(), None);
+        assert_eq!(parser.parse_line("md5 1234  a.txt").unwrap().unwrap().algorithm, Some(Algorithm::MD5));
+
+        // Without the header, the algorithm is part of the checksum.
+        let mut parser = Parser::new(None, false);
+        assert_eq!(parser.parse_line("MD5 1234  a.txt").unwrap().unwrap().checksum, "MD5");
+    }
+
+    #[test]
+    fn test_parse_spaces_in_paths() {
+        // Everything after the two separator characters is the path, spaces included.
+        let mut parser = Parser::new(None, false);
+        let path = |parser: &mut Parser, line: &str| parser.parse_line(line).unwrap().unwrap().path;
+        assert_eq!(path(&mut parser, "bb057481a1b7abc93ad5d70d52e3a55f  my report.pdf"), PathBuf::from("my report.pdf"));
+        assert_eq!(path(&mut parser, "bb057481a1b7abc93ad5d70d52e3a55f  two  spaces.txt"), PathBuf::from("two  spaces.txt"));
+        assert_eq!(path(&mut parser, "bb057481a1b7abc93ad5d70d52e3a55f   lead and trail "), PathBuf::from(" lead and trail "));
+        assert_eq!(path(&mut parser, "bb057481a1b7abc93ad5d70d52e3a55f * binary name"), PathBuf::from(" binary name"));
+    }
+
+    #[test]
+    fn test_parse_algorithm_header() {
+        // The header sets the algorithm of the lines after it, names are case insensitive.
+        let mut parser = Parser::new(None, false);
+        let entry = parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f  before.txt").unwrap().unwrap();
+        assert_eq!(entry.algorithm, None);
+        assert!(parser.parse_line("# algorithm: xxh3_128").unwrap().is_none());
+        let entry = parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f  after.txt").unwrap().unwrap();
+        assert_eq!(entry.algorithm, Some(Algorithm::XXH3_128));
+
+        // Unknown names and lists of algorithms are ignored, as is the header with `-a`.
+        let mut parser = Parser::new(None, false);
+        parser.parse_line("# algorithm: nonsense").unwrap();
+        parser.parse_line("# algorithm: MD5,SHA256").unwrap();
+        assert_eq!(parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f  a.txt").unwrap().unwrap().algorithm, None);
+        let mut parser = Parser::new(Some(Algorithm::MD5), false);
+        parser.parse_line("# algorithm: XXH128").unwrap();
+        assert_eq!(parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f  a.txt").unwrap().unwrap().algorithm, Some(Algorithm::MD5));
+    }
+
+    #[test]
+    fn test_parse_comments_and_blank_lines() {
+        let mut parser = Parser::new(None, false);
+        assert!(parser.parse_line("").unwrap().is_none());
+        assert!(parser.parse_line("   \t").unwrap().is_none());
+        assert!(parser.parse_line("  # indented comment").unwrap().is_none());
+        assert!(parser.parse_line("#no space").unwrap().is_none());
+        assert!(parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f  a.txt").unwrap().is_some());
+
+        // The hashdeep magic line still counts as the first one after blank lines.
+        let mut parser = Parser::new(None, false);
+        parser.parse_line("").unwrap();
+        parser.parse_line("%%%% HASHDEEP-1.0").unwrap();
+        parser.parse_line("%%%% size,md5,filename").unwrap();
+        parser.parse_line("").unwrap();
+        let entry = parser.parse_line("12,bb057481a1b7abc93ad5d70d52e3a55f,a.txt").unwrap().unwrap();
+        assert_eq!(entry.size, Some(12));
+    }
+
+    #[test]
+    fn test_parse_hashdeep() {
+        let mut parser = Parser::new(None, false);
+        assert!(parser.parse_line("%%%% HASHDEEP-1.0").unwrap().is_none());
+        assert!(parser.parse_line("%%%% size,md5,sha256,filename").unwrap().is_none());
+        assert!(parser.parse_line("## Invoked from: /tmp").unwrap().is_none());
+        assert!(parser.parse_line("##").unwrap().is_none());
+        let entry = parser.parse_line("12,bb057481a1b7abc93ad5d70d52e3a55f,423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b,a,b.txt").unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("a,b.txt"));
+        assert_eq!(entry.checksum, "bb057481a1b7abc93ad5d70d52e3a55f");
+        assert_eq!(entry.size, Some(12));
+        assert_eq!(entry.algorithm, Some(Algorithm::MD5));
+        assert!(parser.parse_line("x,bb057481a1b7abc93ad5d70d52e3a55f,a.txt").is_err());
+
+        let mut parser = Parser::new(Some(Algorithm::SHA256), false);
+        parser.parse_line("%%%% HASHDEEP-1.0").unwrap();
+        parser.parse_line("%%%% size,md5,sha256,filename").unwrap();
+        let entry = parser.parse_line("12,bb057481a1b7abc93ad5d70d52e3a55f,423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b,a.txt").unwrap().unwrap();
+        assert_eq!(entry.checksum, "423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b");
+        assert_eq!(entry.algorithm, Some(Algorithm::SHA256));
+        let entry = parser.parse_line("\\12,bb057481a1b7abc93ad5d70d52e3a55f,423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b,a\\nb\\\\c.txt").unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("a\nb\\c.txt"));
+    }
+
+    #[test]
+    fn test_parse_hashdeep_missing_header() {
+        let mut parser = Parser::new(None, false);
+        parser.parse_line("%%%% HASHDEEP-1.0").unwrap();
+        assert!(parser.parse_line("12,bb057481a1b7abc93ad5d70d52e3a55f,a.txt").is_err());
+    }
+
+    #[test]
+    fn test_parse_tagged() {
+        let mut parser = Parser::new(None, false);
+        let entry = parser.parse_line("MD5 (a (1).txt) = bb057481a1b7abc93ad5d70d52e3a55f").unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("a (1).txt"));
+        assert_eq!(entry.checksum, "bb057481a1b7abc93ad5d70d52e3a55f");
+        assert_eq!(entry.algorithm, Some(Algorithm::MD5));
+
+        let mut parser = Parser::new(Some(Algorithm::SHA256), false);
+        assert!(parser.parse_line("MD5 (a.txt) = bb057481a1b7abc93ad5d70d52e3a55f").unwrap().is_none());
+        let entry = parser.parse_line("SHA256 (a.txt) = 423df0dab6a97c46239d196ad6f610edf5484650e9e7085634045e8b3fc19d0b").unwrap().unwrap();
+        assert_eq!(entry.algorithm, Some(Algorithm::SHA256));
+    }
+
+    #[test]
+    fn test_parse_binary_mode() {
+        let mut parser = Parser::new(None, false);
+        let entry = parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f *a.txt").unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("a.txt"));
+        // With the text mode marker, a leading `*` belongs to the file name.
+        let entry = parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f  *a.txt").unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("*a.txt"));
+        let entry = parser.parse_line("bb057481a1b7abc93ad5d70d52e3a55f **a.txt").unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("*a.txt"));
+    }
+
+    #[test]
+    fn test_parse_escaped() {
+        // Lines as written by `sha256sum` and `sha256sum --tag` for awkward file names.
+        let mut parser = Parser::new(None, false);
+        let entry = parser.parse_line("\\2d711642b726b04401627ca9fbac32f5c8530fb1903cc4db02258717921a4881  a\\nb").unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("a\nb"));
+        let entry = parser.parse_line("\\2d711642b726b04401627ca9fbac32f5c8530fb1903cc4db02258717921a4881  c\\\\d").unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("c\\d"));
+        let entry = parser.parse_line("2d711642b726b04401627ca9fbac32f5c8530fb1903cc4db02258717921a4881   lead ").unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from(" lead "));
+        let entry = parser.parse_line("\\SHA256 (a\\nb) = 2d711642b726b04401627ca9fbac32f5c8530fb1903cc4db02258717921a4881").unwrap().unwrap();
+        assert_eq!(entry.path, PathBuf::from("a\nb"));
+        assert!(parser.parse_line("\\2d711642b726b04401627ca9fbac32f5c8530fb1903cc4db02258717921a4881  a\\tb").is_err());
+
+        // NUL-terminated records are never escaped.
+        let mut parser = Parser::new(None, true);
+        let entry = parser.parse_line("\\2d711642b726b04401627ca9fbac32f5c8530fb1903cc4db02258717921a4881  a\\nb").unwrap().unwrap();
+        assert_eq!(entry.checksum, "\\2d711642b726b04401627ca9fbac32f5c8530fb1903cc4db02258717921a4881");
+    }
+
+    #[test]
+    fn test_read_records() {
+        let input = "h1  a\nb\0h2  c\0h3  d";
+        let records: Vec<String> = read_records(Box::new(input.as_bytes()), true).map(|r| r.unwrap()).collect();
+        assert_eq!(records, vec!["h1  a\nb", "h2  c", "h3  d"]);
+        let records: Vec<String> = read_records(Box::new(input.as_bytes()), false).map(|r| r.unwrap()).collect();
+        assert_eq!(records, vec!["h1  a", "b\0h2  c\0h3  d"]);
+        assert!(read_records(Box::new(&b"h1  \xff\0"[..]), true).next().unwrap().is_err());
+
+        let input = "\u{feff}h1  a\r\nh2  \u{feff}b\r\n";
+        let records: Vec<String> = read_records(Box::new(input.as_bytes()), false).map(|r| r.unwrap()).collect();
+        assert_eq!(records, vec!["h1  a", "h2  \u{feff}b"]);
+    }
+}
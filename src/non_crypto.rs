@@ -0,0 +1,125 @@
+//! Non-cryptographic checksums, and BLAKE3 which only implements a later `digest`, wrapped in the
+//! `digest` traits, so they can be boxed as `DynDigest`.
+
+// digest 0.9 is built on generic-array 0.14, whose latest releases deprecate `GenericArray`.
+#![allow(deprecated)]
+
+use std::hash::Hasher;
+use digest::consts::{U16, U32, U4, U8};
+use digest::generic_array::GenericArray;
+use digest::{FixedOutputDirty, Reset, Update};
+
+#[derive(Clone, Default)]
+pub struct Crc32(crc32fast::Hasher);
+
+impl Update for Crc32 {
+    fn update(&mut self, data: impl AsRef<[u8]>) {
+        self.0.update(data.as_ref());
+    }
+}
+
+impl FixedOutputDirty for Crc32 {
+    type OutputSize = U4;
+
+    fn finalize_into_dirty(&mut self, out: &mut GenericArray<u8, U4>) {
+        out.copy_from_slice(&self.0.clone().finalize().to_be_bytes());
+    }
+}
+
+impl Reset for Crc32 {
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Adler32(adler::Adler32);
+
+impl Update for Adler32 {
+    fn update(&mut self, data: impl AsRef<[u8]>) {
+        self.0.write_slice(data.as_ref());
+    }
+}
+
+impl FixedOutputDirty for Adler32 {
+    type OutputSize = U4;
+
+    fn finalize_into_dirty(&mut self, out: &mut GenericArray<u8, U4>) {
+        out.copy_from_slice(&self.0.checksum().to_be_bytes());
+    }
+}
+
+impl Reset for Adler32 {
+    fn reset(&mut self) {
+        self.0 = adler::Adler32::new();
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct XxHash64(twox_hash::XxHash64);
+
+impl Update for XxHash64 {
+    fn update(&mut self, data: impl AsRef<[u8]>) {
+        self.0.write(data.as_ref());
+    }
+}
+
+impl FixedOutputDirty for XxHash64 {
+    type OutputSize = U8;
+
+    fn finalize_into_dirty(&mut self, out: &mut GenericArray<u8, U8>) {
+        out.copy_from_slice(&self.0.finish().to_be_bytes());
+    }
+}
+
+impl Reset for XxHash64 {
+    fn reset(&mut self) {
+        self.0 = twox_hash::XxHash64::default();
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Xxh3128(twox_hash::XxHash3_128);
+
+impl Update for Xxh3128 {
+    fn update(&mut self, data: impl AsRef<[u8]>) {
+        self.0.write(data.as_ref());
+    }
+}
+
+impl FixedOutputDirty for Xxh3128 {
+    type OutputSize = U16;
+
+    fn finalize_into_dirty(&mut self, out: &mut GenericArray<u8, U16>) {
+        out.copy_from_slice(&self.0.finish_128().to_be_bytes());
+    }
+}
+
+impl Reset for Xxh3128 {
+    fn reset(&mut self) {
+        self.0 = twox_hash::XxHash3_128::default();
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Blake3(blake3::Hasher);
+
+impl Update for Blake3 {
+    fn update(&mut self, data: impl AsRef<[u8]>) {
+        self.0.update(data.as_ref());
+    }
+}
+
+impl FixedOutputDirty for Blake3 {
+    type OutputSize = U32;
+
+    fn finalize_into_dirty(&mut self, out: &mut GenericArray<u8, U32>) {
+        out.copy_from_slice(self.0.finalize().as_bytes());
+    }
+}
+
+impl Reset for Blake3 {
+    fn reset(&mut self) {
+        self.0.reset();
+    }
+}
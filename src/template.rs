@@ -0,0 +1,128 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::SystemTime;
+use chksum::{AppError, Algorithm};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Placeholder {
+    Hash,
+    Algorithm,
+    Path,
+    Size,
+    Mtime,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// A record layout like `{hash}  {path}`, checked when the command line is parsed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    pub fn render(&self, path: &Path, checksum: &str, algorithm: Algorithm, size: u64, modified: SystemTime) -> String {
+        let mut output = String::new();
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Literal(s) => output.push_str(s),
+                Segment::Placeholder(Placeholder::Hash) => output.push_str(checksum),
+                Segment::Placeholder(Placeholder::Algorithm) => write!(output, "{}", algorithm).expect("Internal error."),
+                Segment::Placeholder(Placeholder::Path) => write!(output, "{}", path.display()).expect("Internal error."),
+                Segment::Placeholder(Placeholder::Size) => write!(output, "{}", size).expect("Internal error."),
+                Segment::Placeholder(Placeholder::Mtime) => {
+                    write!(output, "{}", humantime::format_rfc3339_seconds(modified)).expect("Internal error.")
+                }
+            }
+        }
+        output
+    }
+}
+
+impl FromStr for Template {
+    type Err = AppError;
+
+    /// `{{` and `}}` stand for literal braces.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AppError::InvalidTemplateError(s.to_owned());
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(invalid()),
+                        }
+                    }
+                    let placeholder = match name.as_str() {
+                        "hash" => Placeholder::Hash,
+                        "algorithm" => Placeholder::Algorithm,
+                        "path" => Placeholder::Path,
+                        "size" => Placeholder::Size,
+                        "mtime" => Placeholder::Mtime,
+                        _ => return Err(invalid()),
+                    };
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder(placeholder));
+                }
+                '}' => return Err(invalid()),
+                _ => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(Self { segments })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use std::time::{Duration, UNIX_EPOCH};
+    use structopt::StructOpt;
+    use chksum::Algorithm;
+    use crate::cmd_line::GenerationOpt;
+    use crate::template::Template;
+
+    #[test]
+    fn test_render() {
+        let modified = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let template: Template = "{hash}  {path}".parse().unwrap();
+        assert_eq!(template.render(Path::new("a/b.txt"), "abcd", Algorithm::MD5, 12, modified), "abcd  a/b.txt");
+        let template: Template = "{algorithm}:{hash},{size},{path},{mtime}".parse().unwrap();
+        assert_eq!(template.render(Path::new("a/b.txt"), "abcd", Algorithm::SHA256, 12, modified),
+                   "SHA256:abcd,12,a/b.txt,2020-09-13T12:26:40Z");
+        let template: Template = "{{{hash}}}".parse().unwrap();
+        assert_eq!(template.render(Path::new("a"), "abcd", Algorithm::MD5, 0, modified), "{abcd}");
+    }
+
+    #[test]
+    fn test_invalid_template() {
+        assert!("{hash} {bogus}".parse::<Template>().is_err());
+        assert!("{hash".parse::<Template>().is_err());
+        assert!("hash}".parse::<Template>().is_err());
+        // Rejected while parsing the command line, before any file is read.
+        assert!(GenerationOpt::from_iter_safe(&["g", "--format-template", "{bogus}  {path}"]).is_err());
+    }
+}
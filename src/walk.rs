@@ -0,0 +1,904 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::time::SystemTime;
+use anyhow::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+use threadpool::ThreadPool;
+use walkdir::{DirEntry, WalkDir};
+use crate::checksum::{DEFAULT_BUFFER_SIZE, Algorithm, ByteRange, ReadGate, calculate_file_checksums};
+use crate::error::AppError;
+
+/// Per-directory ignore file read with `gitignore`, in the same syntax as `.gitignore`.
+const CUSTOM_IGNORE_FILE: &str = ".chksumignore";
+
+/// Files queued per thread at most, the walk waits for results beyond that so huge trees don't
+/// pile up jobs in memory.
+const JOBS_PER_THREAD: usize = 4;
+
+/// Options for walking directories and hashing the files found.
+#[derive(Clone, Debug)]
+pub struct ManifestOptions {
+    /// Zero means one thread per physical core.
+    pub num_threads: usize,
+    /// How many files are read at once, zero means as many as there are threads. Only reading
+    /// waits for its turn, threads beyond this still hash what they've read meanwhile.
+    pub io_concurrency: usize,
+    /// Files that are skipped, compared by their canonical paths.
+    pub exclude: Vec<PathBuf>,
+    /// Files with these names are skipped in every directory.
+    pub exclude_file_names: Vec<OsString>,
+    /// Files whose last extension is one of these, in lowercase and without the dot, are skipped.
+    pub exclude_extensions: Vec<String>,
+    /// Only hashes files matching one of these patterns if any are given, relative to the directory
+    /// being walked.
+    pub include_globs: Vec<Glob>,
+    /// Skips files matching any of these patterns, taking precedence over `include_globs`. Walking
+    /// doesn't descend into matching directories, unless an include pattern names a path below one.
+    pub exclude_globs: Vec<Glob>,
+    /// Skips files ignored by `.gitignore`, `.ignore` and `.chksumignore` files.
+    pub gitignore: bool,
+    /// Follows symbolic links to files and directories, otherwise they are skipped.
+    pub follow_links: bool,
+    /// Doesn't descend into directories on other file systems.
+    pub same_file_system: bool,
+    /// Skips files and directories whose names start with `.`, without descending into the latter.
+    pub skip_hidden: bool,
+    /// Limits how deep below the given directories files are looked for.
+    pub max_depth: Option<usize>,
+    /// Hashes the decompressed content of `.gz` files instead of the files themselves.
+    pub decompress: bool,
+    /// Only this part of each file is hashed.
+    pub range: ByteRange,
+    /// Skips files less deep than this, files directly in the given directories are at depth 1.
+    pub min_depth: usize,
+    /// Size of the read buffer, in bytes.
+    pub buffer_size: usize,
+    /// Calculates HMACs with this key instead of plain digests.
+    pub hmac_key: Option<Vec<u8>>,
+    /// Skips files smaller than this many bytes. Sizes are checked while walking, a file that
+    /// crosses a limit before it's hashed is still hashed and recorded with the size it has then.
+    pub min_size: u64,
+    /// Skips files larger than this many bytes.
+    pub max_size: Option<u64>,
+    /// Takes exactly these files instead of walking the directories, e.g. for `--files-from`.
+    pub files: Option<Vec<PathBuf>>,
+    /// Checksums from an earlier run, by path as walked. Files whose size and modification time
+    /// still match aren't hashed again but reported as `WalkEvent::Unchanged`.
+    pub previous: HashMap<PathBuf, FileChecksums>,
+    /// Once set, no more files are walked, files already queued are still hashed and reported.
+    pub interrupted: Option<Arc<AtomicBool>>,
+}
+
+impl Default for ManifestOptions {
+    fn default() -> Self {
+        Self {
+            num_threads: num_cpus::get_physical(),
+            io_concurrency: 0,
+            exclude: Vec::new(),
+            exclude_file_names: Vec::new(),
+            exclude_extensions: Vec::new(),
+            include_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            gitignore: false,
+            follow_links: true,
+            same_file_system: true,
+            skip_hidden: false,
+            max_depth: None,
+            min_depth: 0,
+            decompress: false,
+            range: ByteRange::default(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            hmac_key: None,
+            min_size: 0,
+            max_size: None,
+            files: None,
+            previous: HashMap::new(),
+            interrupted: None,
+        }
+    }
+}
+
+/// The checksums of a file, in the order of the requested algorithms.
+#[derive(Clone, Debug)]
+pub struct FileChecksums {
+    pub path: PathBuf,
+    pub checksums: Vec<Vec<u8>>,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Reported by `walk_checksums` as the walk goes on.
+#[derive(Debug)]
+pub enum WalkEvent {
+    /// A file has been found and queued for hashing.
+    Queued(PathBuf),
+    /// A queued file has been hashed or failed to.
+    Hashed(Result<FileChecksums>),
+    /// A file matches its entry in `ManifestOptions::previous`, whose checksums are carried over.
+    Unchanged(FileChecksums),
+    /// An entry couldn't be read during the walk.
+    Error(anyhow::Error),
+}
+
+/// Counts down the reads that may still start, threads wait in `acquire` once there are none.
+struct Semaphore {
+    permits: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Gives its permit back when dropped.
+struct Permit<'a>(&'a Semaphore);
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Self { permits: Mutex::new(permits), released: Condvar::new() }
+    }
+
+    fn acquire(&self) -> Permit<'_> {
+        let mut permits = self.permits.lock().expect("Internal error.");
+        while *permits == 0 {
+            permits = self.released.wait(permits).expect("Internal error.");
+        }
+        *permits -= 1;
+        Permit(self)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.permits.lock().expect("Internal error.") += 1;
+        self.0.released.notify_one();
+    }
+}
+
+struct Exclusion {
+    e: HashSet<PathBuf>,
+}
+
+impl Exclusion {
+    fn new(excludes: &[PathBuf]) -> Self {
+        Self {
+            e: excludes.iter().filter_map(|p| p.canonicalize().ok()).collect()
+        }
+    }
+
+    fn is_excluded(&self, path: &Path) -> Result<bool> {
+        let c = path.canonicalize()?;
+        Ok(self.e.contains(&c))
+    }
+}
+
+fn build_glob_set(globs: &[Glob]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs.iter() {
+        builder.add(glob.clone());
+    }
+    Ok(builder.build()?)
+}
+
+/// Directories a pattern can only match below, from its part before the first wildcard.
+fn literal_dir(glob: &Glob) -> &Path {
+    let pattern = glob.glob();
+    let literal = &pattern[..pattern.find(|c| "*?[{\\".contains(c)).unwrap_or(pattern.len())];
+    Path::new(&literal[..literal.rfind('/').unwrap_or(0)])
+}
+
+/// Include and exclude patterns, matched against paths relative to the directory being walked.
+struct GlobFilter {
+    includes: GlobSet,
+    excludes: GlobSet,
+    has_includes: bool,
+    include_dirs: Vec<PathBuf>,
+}
+
+impl GlobFilter {
+    fn new(opts: &ManifestOptions) -> Result<Self> {
+        Ok(Self {
+            includes: build_glob_set(&opts.include_globs)?,
+            excludes: build_glob_set(&opts.exclude_globs)?,
+            has_includes: !opts.include_globs.is_empty(),
+            include_dirs: opts.include_globs.iter().map(|g| literal_dir(g).to_owned()).collect(),
+        })
+    }
+
+    /// Whether nothing below an excluded directory can be selected, so it needn't be walked.
+    fn prunes(&self, relative_dir: &Path) -> bool {
+        self.excludes.is_match(relative_dir)
+            && !self.include_dirs.iter().any(|d| d.starts_with(relative_dir) && d != relative_dir)
+    }
+
+    fn selects(&self, relative: &Path) -> bool {
+        if self.excludes.is_match(relative) {
+            return false;
+        }
+        // Files in an excluded directory are only kept when explicitly included.
+        let in_excluded_dir = relative.ancestors().skip(1)
+            .any(|d| !d.as_os_str().is_empty() && self.excludes.is_match(d));
+        match in_excluded_dir {
+            true => self.includes.is_match(relative),
+            false => !self.has_includes || self.includes.is_match(relative),
+        }
+    }
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    entry.file_name().to_str().is_some_and(|n| n.starts_with('.'))
+}
+
+/// Names the file in an error reading it, telling lacking permissions apart as they're expected in
+/// some trees.
+fn read_error(path: &Path, e: anyhow::Error) -> AppError {
+    match e.downcast_ref::<std::io::Error>() {
+        Some(io) if io.kind() == std::io::ErrorKind::PermissionDenied => AppError::PermissionDeniedError(path.to_owned()),
+        _ => AppError::ReadError(path.to_owned(), e.to_string()),
+    }
+}
+
+fn file_checksums(path: &Path, algorithms: &[Algorithm], key: Option<&[u8]>, buffer_size: usize, decompress: bool, range: ByteRange, gate: &ReadGate) -> Result<FileChecksums> {
+    if path.is_dir() || !path.is_file() {
+        return Err(AppError::InvalidFileError(path.to_path_buf()).into());
+    }
+    // Errors from reading the file don't say which one it was.
+    let read_error = |e: anyhow::Error| read_error(path, e);
+    let metadata = path.metadata().map_err(|e| read_error(e.into()))?;
+    Ok(FileChecksums {
+        path: path.to_owned(),
+        checksums: calculate_file_checksums(path, algorithms, key, buffer_size, decompress, range, gate).map_err(read_error)?,
+        size: metadata.len(),
+        modified: metadata.modified().map_err(|e| read_error(e.into()))?,
+    })
+}
+
+/// Lists everything below `dir`, skipping what ignore files say when `gitignore` is set and not
+/// descending into directories pruned by `globs`.
+fn walk_directory<'a>(dir: &Path, opts: &'a ManifestOptions, globs: &Arc<GlobFilter>) -> Box<dyn Iterator<Item = Result<PathBuf>> + 'a> {
+    let root = dir.to_owned();
+    let globs = globs.clone();
+    let pruned = move |path: &Path, is_dir: bool| {
+        is_dir && path != root && globs.prunes(path.strip_prefix(&root).unwrap_or(path))
+    };
+    if opts.gitignore {
+        let mut builder = WalkBuilder::new(dir);
+        builder.follow_links(opts.follow_links)
+            .same_file_system(opts.same_file_system)
+            .max_depth(opts.max_depth)
+            .hidden(opts.skip_hidden)
+            // Ignore files apply outside of git repositories as well.
+            .require_git(false)
+            .add_custom_ignore_filename(CUSTOM_IGNORE_FILE)
+            .filter_entry(move |e| !pruned(e.path(), e.file_type().is_some_and(|t| t.is_dir())));
+        return Box::new(builder.build()
+            .filter(move |e| e.as_ref().map_or(true, |e| e.depth() >= opts.min_depth))
+            .map(|e| Ok(e?.into_path())));
+    }
+    let walker = WalkDir::new(dir)
+        .follow_links(opts.follow_links)
+        .same_file_system(opts.same_file_system)
+        .min_depth(opts.min_depth);
+    let walker = match opts.max_depth {
+        Some(depth) => walker.max_depth(depth),
+        None => walker,
+    };
+    // The given directories themselves are always walked, even `.` or hidden ones.
+    Box::new(walker.into_iter()
+        .filter_entry(move |e| (!opts.skip_hidden || e.depth() == 0 || !is_hidden(e)) && !pruned(e.path(), e.file_type().is_dir()))
+        .map(|e| Ok(e?.into_path())))
+}
+
+/// Calls `callback` with every file under the directories, or listed in `opts.files`, selected by
+/// `opts`, or with the errors met on the way, without reading any of them.
+pub fn walk_files<F>(directories: &[PathBuf], opts: &ManifestOptions, mut callback: F) -> Result<()>
+    where F: FnMut(Result<PathBuf>)
+{
+    let globs = Arc::new(GlobFilter::new(opts)?);
+    let exclusion = Exclusion::new(&opts.exclude);
+    // Listed files are taken as they are, with nothing to be relative to.
+    let entries: Box<dyn Iterator<Item = (PathBuf, Result<PathBuf>)>> = match &opts.files {
+        Some(files) => Box::new(files.iter().map(|path| match path.is_file() {
+            true => (PathBuf::new(), Ok(path.clone())),
+            false => (PathBuf::new(), Err(AppError::InvalidFileError(path.clone()).into())),
+        })),
+        None => Box::new(directories.iter().flat_map(|dir| walk_directory(dir, opts, &globs).map(move |entry| (dir.clone(), entry)))),
+    };
+    for (dir, entry) in entries {
+        if opts.interrupted.as_ref().is_some_and(|i| i.load(Ordering::Relaxed)) {
+            break;
+        }
+        let path = match entry {
+            Ok(path) => path,
+            Err(e) => {
+                callback(Err(e));
+                continue;
+            }
+        };
+        // Looks through links like `is_file`, the size is taken from the same call.
+        let metadata = match path.metadata() {
+            Ok(metadata) if metadata.is_file() => metadata,
+            _ => continue,
+        };
+        if opts.exclude_file_names.iter().any(|n| Some(n.as_os_str()) == path.file_name()) {
+            continue;
+        }
+        // Only the last one counts, `archive.tar.gz` has the extension `gz` but not `tar`.
+        if path.extension().is_some_and(|e| opts.exclude_extensions.contains(&e.to_string_lossy().to_lowercase())) {
+            continue;
+        }
+        if metadata.len() < opts.min_size || opts.max_size.is_some_and(|max| metadata.len() > max) {
+            continue;
+        }
+        // `is_file` looks through links, which aren't wanted when not following them.
+        if !opts.follow_links && path.symlink_metadata().is_ok_and(|m| m.file_type().is_symlink()) {
+            continue;
+        }
+        // Patterns match the path relative to the directory being walked, or the name of a file
+        // given in its place.
+        let relative = match path.strip_prefix(dir) {
+            Ok(relative) if relative.as_os_str().is_empty() => Path::new(path.file_name().unwrap_or_default()),
+            Ok(relative) => relative,
+            Err(_) => &path,
+        };
+        if !globs.selects(relative) {
+            continue;
+        }
+        match exclusion.is_excluded(&path) {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(err) => callback(Err(err)),
+        }
+        callback(Ok(path));
+    }
+    Ok(())
+}
+
+/// Walks the directories and hashes every file found in parallel, reporting progress and results
+/// through `callback` on the calling thread. Results arrive in no particular order.
+pub fn walk_checksums<F>(directories: &[PathBuf], algorithms: &[Algorithm], opts: &ManifestOptions, callback: F) -> Result<()>
+    where F: FnMut(WalkEvent)
+{
+    let reads = Semaphore::new(read_permits(opts));
+    gated_walk_checksums(directories, algorithms, opts, Arc::new(move |read: &mut dyn FnMut()| {
+        let _permit = reads.acquire();
+        read()
+    }), callback)
+}
+
+fn thread_count(opts: &ManifestOptions) -> usize {
+    match opts.num_threads {
+        0 => num_cpus::get_physical(),
+        n => n,
+    }
+}
+
+/// How many files may be read at once.
+fn read_permits(opts: &ManifestOptions) -> usize {
+    match opts.io_concurrency {
+        0 => thread_count(opts),
+        n => n,
+    }
+}
+
+/// Like `walk_checksums`, with every read from a file done through `gate`.
+fn gated_walk_checksums<F>(directories: &[PathBuf], algorithms: &[Algorithm], opts: &ManifestOptions, gate: Arc<ReadGate>, mut callback: F) -> Result<()>
+    where F: FnMut(WalkEvent)
+{
+    let num_threads = thread_count(opts);
+    let pool = ThreadPool::new(num_threads);
+    {
+        let (tx, rx) = channel();
+        // Shared by all jobs rather than copied into each.
+        let algorithms: Arc<[Algorithm]> = algorithms.into();
+        let key: Option<Arc<[u8]>> = opts.hmac_key.as_deref().map(Arc::from);
+        let mut in_flight: usize = 0;
+        walk_files(directories, opts, |entry| {
+            let path = match entry {
+                Ok(path) => path,
+                Err(e) => return callback(WalkEvent::Error(e)),
+            };
+            if let Some(previous) = opts.previous.get(&path) {
+                let metadata = path.metadata();
+                if metadata.is_ok_and(|m| m.len() == previous.size && m.modified().is_ok_and(|t| t == previous.modified)) {
+                    return callback(WalkEvent::Unchanged(previous.clone()));
+                }
+            }
+            let tx = tx.clone();
+            let algorithms = algorithms.clone();
+            let key = key.clone();
+            let (buffer_size, decompress, range) = (opts.buffer_size, opts.decompress, opts.range);
+            let gate = gate.clone();
+            callback(WalkEvent::Queued(path.clone()));
+            pool.execute(move || {
+                let result = file_checksums(&path, &algorithms, key.as_deref(), buffer_size, decompress, range, &*gate);
+                tx.send(result).expect("Internal error.");
+            });
+            in_flight += 1;
+            // Results are reported as they come, not only once everything has been queued.
+            for result in rx.try_iter() {
+                in_flight -= 1;
+                callback(WalkEvent::Hashed(result));
+            }
+            while in_flight >= num_threads * JOBS_PER_THREAD {
+                in_flight -= 1;
+                callback(WalkEvent::Hashed(rx.recv().expect("Internal error.")));
+            }
+        })?;
+
+        // Every job holds a sender, so the channel closes once the last one is done.
+        drop(tx);
+        for result in rx {
+            callback(WalkEvent::Hashed(result));
+        }
+    }
+    pool.join();
+    Ok(())
+}
+
+/// Calculates the checksums of all files under `dir`, sorted by path. Fails if any file can't be
+/// read.
+pub fn generate_manifest(dir: &Path, algorithm: Algorithm, opts: &ManifestOptions) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+    let mut results = Vec::new();
+    let mut error = None;
+    walk_checksums(&[dir.to_owned()], &[algorithm], opts, |event| match event {
+        WalkEvent::Queued(_) => {}
+        WalkEvent::Hashed(Ok(mut c)) | WalkEvent::Unchanged(mut c) => results.push((c.path, c.checksums.remove(0))),
+        WalkEvent::Hashed(Err(e)) | WalkEvent::Error(e) => {
+            error.get_or_insert(e);
+        }
+    })?;
+    if let Some(e) = error {
+        return Err(e);
+    }
+    results.sort_by(|e1, e2| e1.0.cmp(&e2.0));
+    Ok(results)
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::time::{Duration, SystemTime};
+    use globset::Glob;
+    use tempfile::tempdir;
+    use crate::checksum::{Algorithm, Encoding, encode_checksum};
+    use crate::error::AppError;
+    use crate::walk::{
+        JOBS_PER_THREAD, ManifestOptions, Semaphore, read_error, read_permits, WalkEvent, generate_manifest, gated_walk_checksums, walk_checksums, walk_files,
+    };
+
+    #[test]
+    fn test_generate_manifest() {
+        let dir = tempdir().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("a.txt"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("sub").join("b.txt"), "abcdABCD1234").unwrap();
+        fs::write(dir.path().join("c.txt"), "abcdABCD1234").unwrap();
+
+        let opts = ManifestOptions {
+            exclude: vec![dir.path().join("c.txt")],
+            ..ManifestOptions::default()
+        };
+        let manifest = generate_manifest(dir.path(), Algorithm::MD5, &opts).unwrap();
+        assert_eq!(manifest.len(), 2);
+        assert_eq!(manifest[0].0, dir.path().join("a.txt"));
+        assert_eq!(manifest[1].0, dir.path().join("sub").join("b.txt"));
+        assert!(manifest.iter().all(|(_, c)| encode_checksum(c, Encoding::Hex) == "bb057481a1b7abc93ad5d70d52e3a55f"));
+    }
+
+    #[test]
+    fn test_hidden_and_depth() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join(".root");
+        fs::create_dir_all(root.join(".git").join("objects")).unwrap();
+        fs::create_dir_all(root.join("src").join("deep")).unwrap();
+        fs::write(root.join(".git").join("objects").join("x"), "x").unwrap();
+        fs::write(root.join(".gitignore"), "x").unwrap();
+        fs::write(root.join("a.txt"), "x").unwrap();
+        fs::write(root.join("src").join("b.txt"), "x").unwrap();
+        fs::write(root.join("src").join("deep").join("c.txt"), "x").unwrap();
+        let paths = |opts: &ManifestOptions| -> Vec<_> {
+            generate_manifest(&root, Algorithm::MD5, opts).unwrap().into_iter()
+                .map(|(p, _)| p.strip_prefix(&root).unwrap().to_owned())
+                .collect()
+        };
+
+        assert_eq!(paths(&ManifestOptions::default()).len(), 5);
+        // The root is hidden itself but given explicitly, so it's still walked.
+        let opts = ManifestOptions { skip_hidden: true, ..ManifestOptions::default() };
+        assert_eq!(paths(&opts), vec![Path::new("a.txt"), Path::new("src/b.txt"), Path::new("src/deep/c.txt")]);
+        let opts = ManifestOptions { skip_hidden: true, max_depth: Some(2), ..ManifestOptions::default() };
+        assert_eq!(paths(&opts), vec![Path::new("a.txt"), Path::new("src/b.txt")]);
+        let opts = ManifestOptions { max_depth: Some(1), ..ManifestOptions::default() };
+        assert_eq!(paths(&opts), vec![Path::new(".gitignore"), Path::new("a.txt")]);
+    }
+
+    #[test]
+    fn test_ignore_files() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("build")).unwrap();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join(".gitignore"), "*.log\nbuild/\n").unwrap();
+        fs::write(root.join(".chksumignore"), "secret.txt\n").unwrap();
+        for name in &["a.txt", "b.log", "build/out.bin", "secret.txt", "sub/c.txt", "sub/d.tmp"] {
+            fs::write(root.join(name), "x").unwrap();
+        }
+        let paths = |opts: &ManifestOptions| -> Vec<_> {
+            generate_manifest(root, Algorithm::MD5, opts).unwrap().into_iter()
+                .map(|(p, _)| p.strip_prefix(root).unwrap().to_owned())
+                .collect()
+        };
+
+        assert_eq!(paths(&ManifestOptions::default()).len(), 8);
+        let opts = ManifestOptions { gitignore: true, skip_hidden: true, ..ManifestOptions::default() };
+        assert_eq!(paths(&opts), vec![Path::new("a.txt"), Path::new("sub/c.txt"), Path::new("sub/d.tmp")]);
+        let opts = ManifestOptions {
+            gitignore: true,
+            skip_hidden: true,
+            exclude_globs: vec![Glob::new("*.tmp").unwrap()],
+            ..ManifestOptions::default()
+        };
+        assert_eq!(paths(&opts), vec![Path::new("a.txt"), Path::new("sub/c.txt")]);
+        let opts = ManifestOptions { exclude_globs: vec![Glob::new("sub/*").unwrap()], ..ManifestOptions::default() };
+        assert_eq!(paths(&opts).len(), 6);
+    }
+
+    #[test]
+    fn test_exclude_extensions() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        for name in &["a.txt", "b.TMP", "c.Part", "archive.tar.gz", "notes.tar", "Makefile", ".swp"] {
+            fs::write(root.join(name), "x").unwrap();
+        }
+        let paths = |extensions: &[&str]| -> Vec<_> {
+            let opts = ManifestOptions { exclude_extensions: extensions.iter().map(|e| e.to_string()).collect(), ..ManifestOptions::default() };
+            generate_manifest(root, Algorithm::MD5, &opts).unwrap().into_iter()
+                .map(|(p, _)| p.strip_prefix(root).unwrap().to_owned())
+                .collect()
+        };
+
+        // Case doesn't matter, files without an extension are kept, `.swp` is a name rather than one.
+        assert_eq!(paths(&["tmp", "part", "swp"]), vec![Path::new(".swp"), Path::new("Makefile"), Path::new("a.txt"), Path::new("archive.tar.gz"), Path::new("notes.tar")]);
+        assert_eq!(paths(&["gz"]).len(), 6);
+        assert_eq!(paths(&["tar"]), vec![Path::new(".swp"), Path::new("Makefile"), Path::new("a.txt"), Path::new("archive.tar.gz"), Path::new("b.TMP"), Path::new("c.Part")]);
+    }
+
+    #[test]
+    fn test_nested_ignore_files() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        let files = [
+            "a.txt", "a.log", "target/debug/app",
+            "sub/b.txt", "sub/b.tmp", "sub/keep.log",
+            "sub/deep/c.txt", "sub/deep/private/key", "other/d.tmp",
+        ];
+        for name in files {
+            fs::create_dir_all(root.join(name).parent().unwrap()).unwrap();
+            fs::write(root.join(name), "x").unwrap();
+        }
+        // Rules apply below the directory of their file, deeper ones override shallower ones.
+        fs::write(root.join(".gitignore"), "*.log\n/target\n").unwrap();
+        fs::write(root.join("sub/.gitignore"), "!keep.log\n*.tmp\n").unwrap();
+        fs::write(root.join("sub/deep/.ignore"), "private/\n").unwrap();
+        let opts = ManifestOptions { gitignore: true, skip_hidden: true, ..ManifestOptions::default() };
+        let mut paths = Vec::new();
+        walk_files(&[root.to_owned()], &opts, |r| paths.push(r.unwrap().strip_prefix(root).unwrap().to_owned())).unwrap();
+        paths.sort();
+
+        let expected = ["a.txt", "other/d.tmp", "sub/b.txt", "sub/deep/c.txt", "sub/keep.log"];
+        assert_eq!(paths, expected.iter().map(PathBuf::from).collect::<Vec<_>>());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ignored_directories_not_walked() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join("a.txt"), "x").unwrap();
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        // Walking into the loop would be an error.
+        std::os::unix::fs::symlink(root.join("target"), root.join("target/loop")).unwrap();
+        let opts = ManifestOptions { gitignore: true, skip_hidden: true, ..ManifestOptions::default() };
+        let mut paths = Vec::new();
+        walk_files(&[root.to_owned()], &opts, |r| paths.push(r.unwrap())).unwrap();
+
+        assert_eq!(paths, vec![root.join("a.txt")]);
+    }
+
+    #[test]
+    fn test_include_exclude() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("images")).unwrap();
+        for name in &["a.iso", "b.img", "c.tmp", "images/d.img", "images/e.img.tmp"] {
+            fs::write(root.join(name), "x").unwrap();
+        }
+        let paths = |include: &[&str], exclude: &[&str]| -> Vec<_> {
+            let opts = ManifestOptions {
+                include_globs: include.iter().map(|g| Glob::new(g).unwrap()).collect(),
+                exclude_globs: exclude.iter().map(|g| Glob::new(g).unwrap()).collect(),
+                ..ManifestOptions::default()
+            };
+            generate_manifest(root, Algorithm::MD5, &opts).unwrap().into_iter()
+                .map(|(p, _)| p.strip_prefix(root).unwrap().to_owned())
+                .collect()
+        };
+
+        assert_eq!(paths(&["*.iso", "*.img"], &[]), vec![Path::new("a.iso"), Path::new("b.img"), Path::new("images/d.img")]);
+        assert_eq!(paths(&[], &["*.tmp"]), vec![Path::new("a.iso"), Path::new("b.img"), Path::new("images/d.img")]);
+        assert_eq!(paths(&["images/*"], &["*.tmp"]), vec![Path::new("images/d.img")]);
+        // Excludes win over includes.
+        assert!(paths(&["*.tmp"], &["*.tmp"]).is_empty());
+    }
+
+    #[test]
+    fn test_directory_globs() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        for name in ["src/main.rs", "src/main.o", "src/main.rs~", "src/util/mod.rs", "target/debug/main.o", "target/release/app", "README"] {
+            fs::create_dir_all(root.join(name).parent().unwrap()).unwrap();
+            fs::write(root.join(name), "x").unwrap();
+        }
+        let paths = |include: &[&str], exclude: &[&str]| -> Vec<_> {
+            let opts = ManifestOptions {
+                include_globs: include.iter().map(|g| Glob::new(g).unwrap()).collect(),
+                exclude_globs: exclude.iter().map(|g| Glob::new(g).unwrap()).collect(),
+                ..ManifestOptions::default()
+            };
+            generate_manifest(root, Algorithm::MD5, &opts).unwrap().into_iter()
+                .map(|(p, _)| p.strip_prefix(root).unwrap().to_owned())
+                .collect()
+        };
+
+        assert_eq!(paths(&[], &["target", "*.o", "*~"]), vec![Path::new("README"), Path::new("src/main.rs"), Path::new("src/util/mod.rs")]);
+        assert_eq!(paths(&["src/**/*.rs"], &[]), vec![Path::new("src/main.rs"), Path::new("src/util/mod.rs")]);
+        assert_eq!(paths(&[], &["**/util"]).len(), 6);
+        // An include below an excluded directory brings back just what it names.
+        assert_eq!(paths(&["target/release/app"], &["target"]), vec![Path::new("target/release/app")]);
+        assert_eq!(paths(&["target/release/*", "README"], &["target"]), vec![Path::new("README"), Path::new("target/release/app")]);
+        assert_eq!(paths(&["**/*.rs"], &["target", "src/util"]), vec![Path::new("src/main.rs")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pruned_directories() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join("a.txt"), "x").unwrap();
+        // Descending into the link loop gives an error, which a pruned directory never does.
+        std::os::unix::fs::symlink(root.join("target"), root.join("target/loop")).unwrap();
+        let errors = |exclude: &str, gitignore: bool| {
+            let opts = ManifestOptions {
+                exclude_globs: vec![Glob::new(exclude).unwrap()],
+                gitignore,
+                ..ManifestOptions::default()
+            };
+            let mut errors = 0;
+            walk_files(&[root.to_owned()], &opts, |r| errors += r.is_err() as usize).unwrap();
+            errors
+        };
+
+        assert_eq!(errors("*.tmp", false), 1);
+        assert_eq!(errors("target", false), 0);
+        assert_eq!(errors("target", true), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        let outside = dir.path().join("outside");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(root.join("a.txt"), "x").unwrap();
+        fs::write(outside.join("b.txt"), "x").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("linked_dir")).unwrap();
+        std::os::unix::fs::symlink(outside.join("b.txt"), root.join("linked_file")).unwrap();
+        let paths = |opts: &ManifestOptions| -> Vec<_> {
+            generate_manifest(&root, Algorithm::MD5, opts).unwrap().into_iter()
+                .map(|(p, _)| p.strip_prefix(&root).unwrap().to_owned())
+                .collect()
+        };
+
+        assert_eq!(paths(&ManifestOptions::default()), vec![Path::new("a.txt"), Path::new("linked_dir/b.txt"), Path::new("linked_file")]);
+        let opts = ManifestOptions { follow_links: false, ..ManifestOptions::default() };
+        assert_eq!(paths(&opts), vec![Path::new("a.txt")]);
+        let opts = ManifestOptions { follow_links: false, gitignore: true, ..ManifestOptions::default() };
+        assert_eq!(paths(&opts), vec![Path::new("a.txt")]);
+        let opts = ManifestOptions { same_file_system: false, ..ManifestOptions::default() };
+        assert_eq!(paths(&opts).len(), 3);
+    }
+
+    #[test]
+    fn test_interrupted() {
+        let dir = tempdir().unwrap();
+        for i in 0..100 {
+            fs::write(dir.path().join(format!("{}.txt", i)), "x").unwrap();
+        }
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let opts = ManifestOptions { num_threads: 1, interrupted: Some(interrupted.clone()), ..ManifestOptions::default() };
+        let (mut queued, mut hashed) = (0, 0);
+        walk_checksums(&[dir.path().to_owned()], &[Algorithm::MD5], &opts, |event| match event {
+            WalkEvent::Queued(_) => queued += 1,
+            WalkEvent::Hashed(r) => {
+                r.unwrap();
+                hashed += 1;
+                // As if Ctrl-C was pressed while the first file was hashed.
+                interrupted.store(true, Ordering::Relaxed);
+            }
+            _ => unreachable!(),
+        }).unwrap();
+
+        // Whatever was queued is still reported, but the walk stops soon.
+        assert_eq!(hashed, queued);
+        assert!(queued <= JOBS_PER_THREAD + 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_loop() {
+        let dir = tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("a.txt"), "x").unwrap();
+        fs::write(root.join("sub/b.txt"), "x").unwrap();
+        std::os::unix::fs::symlink(root, root.join("sub/up")).unwrap();
+        let walk = |opts: &ManifestOptions| {
+            let (mut files, mut errors) = (Vec::new(), Vec::new());
+            walk_files(&[root.to_owned()], opts, |r| match r {
+                Ok(path) => files.push(path),
+                Err(e) => errors.push(e.to_string()),
+            }).unwrap();
+            files.sort();
+            (files, errors)
+        };
+
+        // The loop is reported once with the link in question, and the walk goes on.
+        for gitignore in [false, true] {
+            let (files, errors) = walk(&ManifestOptions { gitignore, ..ManifestOptions::default() });
+            assert_eq!(files, vec![root.join("a.txt"), root.join("sub/b.txt")]);
+            assert_eq!(errors.len(), 1);
+            assert!(errors[0].contains(&root.join("sub/up").display().to_string()), "{}", errors[0]);
+        }
+        let (files, errors) = walk(&ManifestOptions { follow_links: false, ..ManifestOptions::default() });
+        assert_eq!(files.len(), 2);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_read_error() {
+        let path = Path::new("a.txt");
+        let denied = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        assert!(matches!(read_error(path, denied.into()), AppError::PermissionDeniedError(p) if p == path));
+        let other = std::io::Error::from(std::io::ErrorKind::InvalidData);
+        assert!(matches!(read_error(path, other.into()), AppError::ReadError(..)));
+    }
+
+    #[test]
+    fn test_semaphore() {
+        let semaphore = Arc::new(Semaphore::new(2));
+        let active = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..8).map(|_| {
+            let (semaphore, active, most) = (semaphore.clone(), active.clone(), most.clone());
+            std::thread::spawn(move || {
+                let _permit = semaphore.acquire();
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(20));
+                active.fetch_sub(1, Ordering::SeqCst);
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        assert!(most.load(Ordering::SeqCst) <= 2);
+        assert_eq!(*semaphore.permits.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_io_concurrency() {
+        let dir = tempdir().unwrap();
+        for i in 0..20 {
+            fs::write(dir.path().join(format!("{}.txt", i)), "abcdABCD1234").unwrap();
+        }
+        let opts = ManifestOptions { num_threads: 4, io_concurrency: 1, ..ManifestOptions::default() };
+        let manifest = generate_manifest(dir.path(), Algorithm::MD5, &opts).unwrap();
+        assert_eq!(manifest.len(), 20);
+        assert!(manifest.iter().all(|(_, c)| encode_checksum(c, Encoding::Hex) == "bb057481a1b7abc93ad5d70d52e3a55f"));
+
+        // Counts who holds a permit while reading, a memory mapped file among them.
+        fs::write(dir.path().join("large.bin"), vec![7; 20 * 1024 * 1024]).unwrap();
+        let opts = ManifestOptions { num_threads: 4, io_concurrency: 2, ..ManifestOptions::default() };
+        let reads = Semaphore::new(read_permits(&opts));
+        let (active, most, total) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let gate = {
+            let (active, most, total) = (active.clone(), most.clone(), total.clone());
+            move |read: &mut dyn FnMut()| {
+                let _permit = reads.acquire();
+                most.fetch_max(active.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                total.fetch_add(1, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(1));
+                read();
+                active.fetch_sub(1, Ordering::SeqCst);
+            }
+        };
+        let mut hashed = 0;
+        gated_walk_checksums(&[dir.path().to_owned()], &[Algorithm::MD5], &opts, Arc::new(gate), |event| {
+            if let WalkEvent::Hashed(c) = event {
+                c.unwrap();
+                hashed += 1;
+            }
+        }).unwrap();
+        assert_eq!(hashed, 21);
+        assert!(total.load(Ordering::SeqCst) > 21);
+        assert!(most.load(Ordering::SeqCst) <= opts.io_concurrency);
+        assert_eq!(active.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_previous() {
+        let dir = tempdir().unwrap();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            fs::write(dir.path().join(name), "abcdABCD1234").unwrap();
+        }
+        let walk = |opts: &ManifestOptions| {
+            let (mut hashed, mut unchanged) = (Vec::new(), Vec::new());
+            walk_checksums(&[dir.path().to_owned()], &[Algorithm::MD5], opts, |event| match event {
+                WalkEvent::Hashed(c) => hashed.push(c.unwrap()),
+                WalkEvent::Unchanged(c) => unchanged.push(c),
+                _ => {}
+            }).unwrap();
+            (hashed, unchanged)
+        };
+        let (hashed, _) = walk(&ManifestOptions::default());
+        assert_eq!(hashed.len(), 3);
+
+        // Touching a file is enough to have it hashed again, even with the same content.
+        let touched = dir.path().join("b.txt");
+        fs::File::options().write(true).open(&touched).unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(3600)).unwrap();
+        let opts = ManifestOptions {
+            previous: hashed.into_iter().map(|c| (c.path.clone(), c)).collect(),
+            ..ManifestOptions::default()
+        };
+        let (hashed, unchanged) = walk(&opts);
+        assert_eq!(hashed.iter().map(|c| &c.path).collect::<Vec<_>>(), vec![&touched]);
+        assert_eq!(unchanged.len(), 2);
+        assert!(unchanged.iter().all(|c| encode_checksum(&c.checksums[0], Encoding::Hex) == "bb057481a1b7abc93ad5d70d52e3a55f"));
+    }
+
+    #[test]
+    fn test_bounded_jobs() {
+        let dir = tempdir().unwrap();
+        for i in 0..100 {
+            fs::write(dir.path().join(format!("{}.txt", i)), "abcdABCD1234").unwrap();
+        }
+        for num_threads in [1, 2] {
+            let opts = ManifestOptions { num_threads, ..ManifestOptions::default() };
+            let (mut in_flight, mut max_in_flight, mut hashed) = (0usize, 0, 0);
+            walk_checksums(&[dir.path().to_owned()], &[Algorithm::MD5], &opts, |event| match event {
+                WalkEvent::Queued(_) => {
+                    in_flight += 1;
+                    max_in_flight = max_in_flight.max(in_flight);
+                }
+                WalkEvent::Hashed(_) => {
+                    in_flight -= 1;
+                    hashed += 1;
+                }
+                _ => {}
+            }).unwrap();
+            assert_eq!(hashed, 100);
+            assert!(max_in_flight <= num_threads * JOBS_PER_THREAD);
+        }
+    }
+}